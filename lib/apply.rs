@@ -1,8 +1,17 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+    ops::Range,
+    sync::Arc,
+};
 
 use crate::{
-    parse::{ASTElement, CatOrEl, CategoryEditKind, AST},
-    word::into_phones,
+    common::Rng,
+    parse::{
+        ASTElement, CatOrEl, CategoryEdit, CategoryEditKind, Change, Direction, EnvironmentGroup,
+        Pattern, PatternElement, Predicate, Rule, AST,
+    },
+    word::{into_phones, Casing, CompiledPattern, Match, Word},
 };
 
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
@@ -10,13 +19,70 @@ pub struct Category {
     pub elements: Vec<Vec<String>>,
 }
 
+/// Named conditions on single phones, registered in code and referred to in
+/// patterns as `{:name}`.
+pub type Predicates = HashMap<String, Box<dyn Fn(&str) -> bool>>;
+
 #[derive(Default, Debug)]
 pub struct InterpreterState {
     pub graphs: Vec<String>,
+    pub separators: Vec<String>,
     pub categories: HashMap<String, Category>,
+    /// Any warnings noticed while applying rules.
+    pub warnings: Vec<Warning>,
+    /// The categories referring to categories which weren't defined yet, in
+    /// the order they were edited, to be resolved again once they are.
+    pub(crate) pending: Vec<(String, PendingCategory)>,
+}
+
+/// How to resolve a category again once the categories it refers to are
+/// defined.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PendingCategory {
+    /// Its members before its first edit referring to an undefined category.
+    base: Vec<Vec<String>>,
+    /// Its edits since then, in order.
+    edits: Vec<CategoryEdit>,
+}
+
+impl InterpreterState {
+    /// A state without any categories, for words parsed with the given graphs
+    /// and separators.
+    #[must_use]
+    pub fn new(graphs: Vec<String>, separators: Vec<String>) -> Self {
+        InterpreterState {
+            graphs,
+            separators,
+            ..InterpreterState::default()
+        }
+    }
+
+    /// The category of the given name, if one is defined.
+    #[must_use]
+    pub fn category(&self, name: &str) -> Option<&Category> {
+        self.categories.get(name)
+    }
+
+    /// The members of the category of the given name, each split into its
+    /// phones. References to other categories are expanded when a category is
+    /// defined, or once they are if they weren't yet, so these are the members
+    /// of any nested categories too.
+    #[must_use]
+    pub fn resolve(&self, name: &str) -> Option<Vec<Vec<String>>> {
+        self.category(name)
+            .map(|category| category.elements.clone())
+    }
+
+    /// Whether the given phones make up a member of the category of the given
+    /// name. An undefined category has no members.
+    #[must_use]
+    pub fn contains(&self, name: &str, phones: &[String]) -> bool {
+        self.category(name)
+            .is_some_and(|category| category.elements.iter().any(|member| member == phones))
+    }
 }
 
-fn without<T: PartialEq>(input: Vec<T>, items: Vec<T>) -> Vec<T> {
+fn without<T: PartialEq>(input: Vec<T>, items: &[T]) -> Vec<T> {
     let mut new_input = vec![];
     for item in input {
         if !items.contains(&item) {
@@ -26,11 +92,11 @@ fn without<T: PartialEq>(input: Vec<T>, items: Vec<T>) -> Vec<T> {
     new_input
 }
 
-fn cat_or_els_to_els(
+pub(crate) fn cat_or_els_to_els(
     elements: Vec<CatOrEl>,
     categories: &HashMap<String, Category>,
-    graphs: &Vec<String>,
-    separator: &String,
+    graphs: &[String],
+    separators: &[String],
 ) -> Vec<Vec<String>> {
     use CatOrEl::*;
     let mut new_elements = vec![];
@@ -43,95 +109,3377 @@ fn cat_or_els_to_els(
                     new_elements.append(&mut cat_elements);
                 }
             }
-            El(input) => new_elements.push(into_phones(input, graphs, separator)),
+            El(input) => new_elements.push(into_phones(input, graphs, separators)),
+            Null => new_elements.push(vec![]),
+            Without(name, excluded) => {
+                let excluded = operand_members(excluded, categories, graphs, separators);
+                if let Some(category) = categories.get(&name) {
+                    new_elements.append(&mut without(category.elements.clone(), &excluded));
+                }
+            }
+            Intersection(name, kept) => {
+                let kept = operand_members(kept, categories, graphs, separators);
+                if let Some(category) = categories.get(&name) {
+                    new_elements.extend(
+                        category
+                            .elements
+                            .iter()
+                            .filter(|member| kept.contains(member))
+                            .cloned(),
+                    );
+                }
+            }
         }
     }
 
     new_elements
 }
 
+/// Collects the names of the categories `elements` refer to which aren't among
+/// `categories`, including those in category operations.
+fn undefined_references(
+    elements: &[CatOrEl],
+    categories: &HashMap<String, Category>,
+    undefined: &mut Vec<String>,
+) {
+    use CatOrEl::*;
+
+    for element in elements {
+        match element {
+            Cat(name) => {
+                if !categories.contains_key(name) {
+                    undefined.push(name.clone());
+                }
+            }
+            Without(name, operand) | Intersection(name, operand) => {
+                if !categories.contains_key(name) {
+                    undefined.push(name.clone());
+                }
+                undefined_references(operand, categories, undefined);
+            }
+            El(_) | Null => {}
+        }
+    }
+}
+
+/// Collects the names of the categories `pattern` refers to which aren't among
+/// `categories`, including those in optional parts and lookarounds.
+fn undefined_in_pattern(
+    pattern: &Pattern,
+    categories: &HashMap<String, Category>,
+    undefined: &mut Vec<String>,
+) {
+    use PatternElement::*;
+
+    for element in &pattern.elements {
+        match element {
+            CatRef(name) => {
+                if !categories.contains_key(name) {
+                    undefined.push(name.clone());
+                }
+            }
+            Category(members) | NegatedCategory(members) => {
+                undefined_references(members, categories, undefined);
+            }
+            Optional(inner)
+            | OptionalNonGreedy(inner)
+            | NegativeLookahead(inner)
+            | NegativeLookbehind(inner) => undefined_in_pattern(inner, categories, undefined),
+            _ => {}
+        }
+    }
+}
+
+/// Every pattern of a rule, whether in its target, its changes or its
+/// environments.
+fn rule_patterns(rule: &Rule) -> Vec<&Pattern> {
+    let mut patterns: Vec<&Pattern> = rule.target.patterns.iter().collect();
+    for predicate in &rule.predicates {
+        let exceptions = predicate.change.iter().flat_map(|c| &c.exception);
+        let groups = predicate
+            .environment
+            .iter()
+            .chain(&predicate.exception)
+            .chain(exceptions);
+        patterns.extend(predicate.change.iter().map(|c| &c.pattern));
+        patterns.extend(groups.flat_map(|group| &group.patterns));
+    }
+    patterns
+}
+
+/// The first category a rule refers to anywhere, whether in its target, its
+/// changes or its environments, which isn't among `categories`.
+fn undefined_in_rule(rule: &Rule, categories: &HashMap<String, Category>) -> Option<String> {
+    let mut undefined = vec![];
+    for pattern in rule_patterns(rule) {
+        undefined_in_pattern(pattern, categories, &mut undefined);
+    }
+    undefined.into_iter().next()
+}
+
+/// The first predicate `pattern` refers to which isn't among `predicates`,
+/// including in optional parts and lookarounds.
+fn unregistered_in_pattern<'p>(pattern: &'p Pattern, predicates: &Predicates) -> Option<&'p str> {
+    use PatternElement::*;
+
+    pattern.elements.iter().find_map(|element| match element {
+        PredicateRef(name) => (!predicates.contains_key(name)).then_some(name.as_str()),
+        Optional(inner)
+        | OptionalNonGreedy(inner)
+        | NegativeLookahead(inner)
+        | NegativeLookbehind(inner) => unregistered_in_pattern(inner, predicates),
+        _ => None,
+    })
+}
+
+/// The members of the list on the right of a category operation, like the
+/// `a,e` of `V-a,e`, where a member naming a category stands for its members.
+fn operand_members(
+    elements: Vec<CatOrEl>,
+    categories: &HashMap<String, Category>,
+    graphs: &[String],
+    separators: &[String],
+) -> Vec<Vec<String>> {
+    let elements = elements
+        .into_iter()
+        .map(|element| match element {
+            CatOrEl::El(name) if categories.contains_key(&name) => CatOrEl::Cat(name),
+            element => element,
+        })
+        .collect();
+    cat_or_els_to_els(elements, categories, graphs, separators)
+}
+
+/// The members of a category element of a pattern, resolving any references
+/// to other categories using `categories`.
+pub(crate) fn category_members(
+    element: &PatternElement,
+    categories: &HashMap<String, Category>,
+    graphs: &[String],
+    separators: &[String],
+) -> Vec<Vec<String>> {
+    match element {
+        PatternElement::CatRef(name) => categories
+            .get(name)
+            .map(|category| category.elements.clone())
+            .unwrap_or_default(),
+        PatternElement::Category(members) => {
+            cat_or_els_to_els(members.clone(), categories, graphs, separators)
+        }
+        _ => vec![],
+    }
+}
+
+/// How many times an iterative rule may apply to a word before it's taken to
+/// never stop changing it.
+pub const ITERATION_LIMIT: usize = 1000;
+
+/// Why a single rule couldn't be applied to a word.
+pub(crate) enum RuleError {
+    /// A category in a change which can't be mapped onto the category in the
+    /// target it corresponds to, since they have different numbers of members.
+    MismatchedCategories { target: usize, change: usize },
+    /// An iterative rule was still changing the word after
+    /// [`ITERATION_LIMIT`] applications.
+    IterationLimit,
+    /// The rule refers to a category which isn't defined.
+    UndefinedCategory(String),
+    /// The rule refers to a predicate which isn't registered.
+    UndefinedPredicate(String),
+    /// A change of the rule has an element which can't say what replaces the
+    /// target, as with a rule built without the parser; see
+    /// [`PatternElement::can_replace`].
+    Unreplaceable(PatternElement),
+}
+
+impl RuleError {
+    /// The error for this in the rule at the given index, applied to `word`.
+    pub(crate) fn in_rule(self, rule: usize, word: &Word) -> ApplyError {
+        let word = word.to_string();
+        match self {
+            RuleError::MismatchedCategories { target, change } => {
+                ApplyError::CategoryLengthMismatch {
+                    rule,
+                    word,
+                    target,
+                    change,
+                }
+            }
+            RuleError::IterationLimit => ApplyError::IterationLimit { rule, word },
+            RuleError::UndefinedCategory(category) => ApplyError::UndefinedCategory {
+                rule,
+                word,
+                category,
+            },
+            RuleError::UndefinedPredicate(predicate) => ApplyError::UndefinedPredicate {
+                rule,
+                word,
+                predicate,
+            },
+            RuleError::Unreplaceable(element) => ApplyError::Unreplaceable {
+                rule,
+                word,
+                element: element.to_string(),
+            },
+        }
+    }
+}
+
+/// Builds the phones that replace a single match of a rule's target.
+///
+/// Each `%` in the change stands for the phones the target matched, and each `<`
+/// for the same phones in reverse, as used for metathesis. A `"` copies the
+/// phone of the target at the same position as it in the output.
+///
+/// A repeat count in the change, as in `a{*} > a{#-1}`, refers to the first
+/// repetition in the target; without one, it counts as having matched nothing.
+/// A plain repetition writes the element before it that many times, so
+/// `[C] > %{2}` geminates a consonant.
+///
+/// Each category in the change corresponds to the category in the same place
+/// among the categories of the target, and the member matched there is
+/// replaced by the member at the same index in the change's category, so
+/// `[a,e] > [x,y]` turns `a` into `x` and `e` into `y`.
+fn replacement(
+    change: &Pattern,
+    matches: &[Match],
+    word: &Word,
+    categories: &HashMap<String, Category>,
+) -> Result<Vec<String>, RuleError> {
+    use PatternElement::*;
+
+    let count = matches.iter().find_map(Match::repetitions).unwrap_or(0);
+    let target = match (matches.first(), matches.last()) {
+        (Some(first), Some(last)) => &word.phones[first.range().start..last.range().end],
+        _ => &[],
+    };
+    let mut target_categories = matches.iter().filter_map(|m| match m {
+        // the null category, `[]`, has no members to map from
+        Match::Single(m) if matches!(&m.element, CatRef(_) | Category(_)) => {
+            (m.element != Category(vec![])).then_some(m)
+        }
+        _ => None,
+    });
+    let members = |element| category_members(element, categories, &word.graphs, &word.separators);
+
+    let mut phones = vec![];
+    for (i, element) in change.elements.iter().enumerate() {
+        let times = match change.elements.get(i + 1) {
+            Some(RepeatCount(offset)) => count.saturating_add_signed(*offset),
+            Some(RepeatN(n)) => *n,
+            _ => 1,
+        };
+        let replaced = match element {
+            Text(text) => into_phones(text.clone(), &word.graphs, &word.separators),
+            // the null category, `[]`, replaces the target with nothing
+            Category(elements) if elements.is_empty() => vec![],
+            // the target, exactly as it was matched
+            Target => target.to_vec(),
+            TargetReversed => target.iter().rev().cloned().collect(),
+            Boundary => vec![String::from("#")],
+            // copies the phone of the target at the same position, or its last
+            // phone if the change has already outgrown it
+            Ditto => target
+                .get(phones.len())
+                .or(target.last())
+                .cloned()
+                .into_iter()
+                .collect(),
+            CatRef(_) | Category(_) => {
+                let to = members(element);
+                let Some(source) = target_categories.next() else {
+                    return Err(RuleError::MismatchedCategories {
+                        target: 0,
+                        change: to.len(),
+                    });
+                };
+                let from = members(&source.element);
+                if from.len() != to.len() {
+                    return Err(RuleError::MismatchedCategories {
+                        target: from.len(),
+                        change: to.len(),
+                    });
+                }
+                from.iter()
+                    .position(|member| word.same_phones(&word.phones[source.range.clone()], member))
+                    .map_or_else(Vec::new, |index| to[index].clone())
+            }
+            RepeatCount(_) | RepeatN(_) => vec![],
+            Optional(_)
+            | OptionalNonGreedy(_)
+            | Wildcard(_)
+            | RepeatRange { .. }
+            | RepeatWild(_)
+            | NegatedCategory(_)
+            | PredicateRef(_)
+            | OptionalBoundary
+            | Gap
+            | SyllableBoundary
+            | NegativeLookahead(_)
+            | NegativeLookbehind(_) => return Err(RuleError::Unreplaceable(element.clone())),
+        };
+        for _ in 0..times {
+            phones.extend_from_slice(&replaced);
+        }
+    }
+    Ok(phones)
+}
+
+/// A single match of a rule's target in a word.
+struct TargetMatch {
+    range: Range<usize>,
+    /// Which of the target's patterns matched.
+    alternative: usize,
+    matches: Vec<Match>,
+}
+
+/// An environment split at its gap `_`, with the patterns before and after it
+/// compiled, or `None` for one without a gap.
+type CompiledEnvironment = Option<(CompiledPattern, CompiledPattern)>;
+
+/// Environment groups with each of their environments compiled.
+type CompiledGroups = Vec<Vec<CompiledEnvironment>>;
+
+/// A predicate with its environments and exceptions compiled, and those of
+/// each of its changes.
+struct CompiledPredicate<'r> {
+    predicate: &'r Predicate,
+    environment: CompiledGroups,
+    exception: CompiledGroups,
+    changes: Vec<(&'r Change, CompiledGroups)>,
+}
+
+/// The patterns of a rule compiled for the words it's applied to, so that
+/// they're compiled once per rule rather than at every index of a word.
+struct CompiledRule<'r> {
+    rule: &'r Rule,
+    targets: Vec<CompiledPattern>,
+    predicates: Vec<CompiledPredicate<'r>>,
+}
+
+impl<'r> CompiledRule<'r> {
+    fn new(rule: &'r Rule, graphs: &[String], separators: &[String]) -> Self {
+        let compile = |pattern: &Pattern| CompiledPattern::new(pattern, graphs, separators);
+        let compile_environment = |pattern: &Pattern| {
+            let gap = pattern
+                .elements
+                .iter()
+                .position(|e| *e == PatternElement::Gap)?;
+            let before = Pattern {
+                elements: pattern.elements[..gap].to_vec(),
+            };
+            let after = Pattern {
+                elements: pattern.elements[gap + 1..].to_vec(),
+            };
+            Some((compile(&before), compile(&after)))
+        };
+        let compile_groups = |groups: &[EnvironmentGroup]| {
+            groups
+                .iter()
+                .map(|group| group.patterns.iter().map(compile_environment).collect())
+                .collect()
+        };
+
+        CompiledRule {
+            rule,
+            targets: rule.target.patterns.iter().map(compile).collect(),
+            predicates: rule
+                .predicates
+                .iter()
+                .map(|predicate| CompiledPredicate {
+                    predicate,
+                    environment: compile_groups(&predicate.environment),
+                    exception: compile_groups(&predicate.exception),
+                    changes: predicate
+                        .change
+                        .iter()
+                        .map(|change| (change, compile_groups(&change.exception)))
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Finds every match of any of `patterns` in the word. Where several of them
+/// match at the same index, the longest match wins, and of those as long, the
+/// one whose pattern comes first, so `a, ab > x, y` turns `abc` into `yc`.
+/// Matches never overlap, so which are found depends on the direction they're
+/// looked for in, but they're returned left to right.
+fn find_matches(
+    patterns: &[CompiledPattern],
+    word: &Word,
+    categories: &HashMap<String, Category>,
+    predicates: &Predicates,
+    direction: Direction,
+) -> Vec<TargetMatch> {
+    // the longest match at `index` which ends no later than `limit`
+    let match_at = |index, limit| {
+        patterns
+            .iter()
+            .enumerate()
+            .filter_map(|(alternative, pattern)| {
+                let within = index..limit;
+                let matches =
+                    word.match_compiled_within(pattern, categories, predicates, within)?;
+                let end = matches.last().map_or(index, |m| m.range().end);
+                Some(TargetMatch {
+                    range: index..end,
+                    alternative,
+                    matches,
+                })
+            })
+            // `max_by_key` would take the last of the longest
+            .min_by_key(|found| (std::cmp::Reverse(found.range.len()), found.alternative))
+    };
+    let mut found = vec![];
+    match direction {
+        Direction::LeftToRight => {
+            let limit = word.phones.len();
+            let match_at = |index| match_at(index, limit).map(|next| (next.range.clone(), next));
+            word.scan(match_at, |next| {
+                found.push(next);
+                true
+            });
+        }
+        Direction::RightToLeft => {
+            // each match has to end before the one after it starts
+            let mut limit = word.phones.len();
+            for index in (0..word.phones.len()).rev() {
+                let next = match_at(index, limit).filter(|m| !word.before_start(&m.range));
+                if let Some(next) = next {
+                    limit = index;
+                    found.push(next);
+                }
+            }
+            found.reverse();
+        }
+    }
+    found
+}
+
+/// Whether an environment holds around the given range of the word. The part
+/// of the pattern before the gap `_` has to match right before the range, and
+/// the part after it right after.
+///
+/// The parser rejects environments without exactly one gap; one without any
+/// is taken to always hold.
+fn environment_matches(
+    environment: &CompiledEnvironment,
+    word: &Word,
+    range: &Range<usize>,
+    categories: &HashMap<String, Category>,
+    predicates: &Predicates,
+) -> bool {
+    let Some((before, after)) = environment else {
+        return true;
+    };
+
+    word.match_compiled_ending_at(before, categories, predicates, range.start)
+        .is_some()
+        && word
+            .match_compiled(after, categories, predicates, range.end)
+            .is_some()
+}
+
+/// Whether any of the environment groups holds around the given range of the
+/// word. A group holds if every one of its patterns does.
+fn any_environment_matches(
+    groups: &CompiledGroups,
+    word: &Word,
+    range: &Range<usize>,
+    categories: &HashMap<String, Category>,
+    predicates: &Predicates,
+) -> bool {
+    groups.iter().any(|group| {
+        group.iter().all(|environment| {
+            environment_matches(environment, word, range, categories, predicates)
+        })
+    })
+}
+
+/// Finds the first predicate of the rule which applies to the given match of
+/// its target, that is, one without environments or with an environment that
+/// holds around the match, and none of whose exceptions hold around it.
+fn applicable_predicate<'c, 'r>(
+    rule: &'c CompiledRule<'r>,
+    word: &Word,
+    range: &Range<usize>,
+    categories: &HashMap<String, Category>,
+    predicates: &Predicates,
+) -> Option<&'c CompiledPredicate<'r>> {
+    let holds = |groups| any_environment_matches(groups, word, range, categories, predicates);
+    rule.predicates.iter().find(|predicate| {
+        if !predicate.predicate.environment.is_empty() && !holds(&predicate.environment) {
+            #[cfg(feature = "tracing")]
+            tracing::trace!(
+                environment = ?predicate.predicate.environment,
+                "environment blocked the match"
+            );
+            return false;
+        }
+        if holds(&predicate.exception) {
+            #[cfg(feature = "tracing")]
+            tracing::trace!(
+                exception = ?predicate.predicate.exception,
+                "exception blocked the match"
+            );
+            return false;
+        }
+        true
+    })
+}
+
+/// Finds the change of the predicate for a match of the given alternative of
+/// the target.
+///
+/// With several targets and as many changes, as in `a, e > o, i`, each target
+/// is paired with the change in the same place, and the match is left alone if
+/// that change's own exceptions hold around it. Otherwise, it's the first
+/// change none of whose own exceptions hold, so that in `a > e!_#, o` an `a`
+/// becomes `e`, unless it's word-final, in which case it becomes `o`. A single
+/// change is shared by all the targets.
+fn applicable_change<'r>(
+    predicate: &CompiledPredicate<'r>,
+    targets: usize,
+    alternative: usize,
+    word: &Word,
+    range: &Range<usize>,
+    categories: &HashMap<String, Category>,
+    predicates: &Predicates,
+) -> Option<&'r Change> {
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    let unblocked = |(change, exception): &&(&Change, CompiledGroups)| {
+        let blocked = any_environment_matches(exception, word, range, categories, predicates);
+        #[cfg(feature = "tracing")]
+        if blocked {
+            tracing::trace!(
+                exception = ?change.exception,
+                "exception of the change blocked the match"
+            );
+        }
+        !blocked
+    };
+
+    let change = if targets > 1 && predicate.changes.len() > 1 {
+        predicate.changes.get(alternative).filter(unblocked)
+    } else {
+        predicate.changes.iter().find(unblocked)
+    };
+    change.map(|(change, _)| *change)
+}
+
+/// Applies a single rule to a word, replacing every match of its target.
+///
+/// A match is only replaced if one of the rule's predicates applies to it; see
+/// [`applicable_predicate`]. Comma-separated environments are alternatives, so
+/// `a > e / _i, _u` changes an `a` before either an `i` or a `u`.
+///
+/// If the target has positions, only the matches at those positions are
+/// replaced, counting only the matches some predicate applies to. So
+/// `+ a@2 / _b` inserts before the second `b`, not at the second position in
+/// the word. A right-to-left rule looks for matches from the end of the word,
+/// so `aa > b` turns `aaa` into `ab` rather than `ba`, and counts its positions
+/// from there too.
+///
+/// All matches are replaced at once, so the output of one replacement can't
+/// feed another.
+///
+/// If the rule has a guard the word doesn't satisfy, the word is left as is.
+/// If it's sporadic, each match is only replaced by chance, as decided by `rng`.
+///
+/// An iterative rule is applied over and over to its own output, until it
+/// stops changing the word.
+///
+/// A rule with a cap on its applications stops replacing matches once it's
+/// replaced that many in the word, over all of its iterations.
+///
+/// A rule referring to a category not among `categories`, or a predicate not
+/// among `predicates`, is an error rather than a rule which quietly never
+/// matches.
+pub(crate) fn apply_rule(
+    rule: &Rule,
+    word: &Word,
+    categories: &HashMap<String, Category>,
+    predicates: &Predicates,
+    rng: &mut Rng,
+) -> Result<Word, RuleError> {
+    if let Some(category) = undefined_in_rule(rule, categories) {
+        return Err(RuleError::UndefinedCategory(category));
+    }
+    let unregistered = rule_patterns(rule)
+        .into_iter()
+        .find_map(|pattern| unregistered_in_pattern(pattern, predicates));
+    if let Some(predicate) = unregistered {
+        return Err(RuleError::UndefinedPredicate(predicate.to_string()));
+    }
+    let compiled = CompiledRule::new(rule, &word.graphs, &word.separators);
+    let mut applications = 0;
+    let mut apply_once = |word: &Word| {
+        apply_rule_once(
+            &compiled,
+            word,
+            categories,
+            predicates,
+            rng,
+            &mut applications,
+        )
+    };
+    if !rule.iterative {
+        return apply_once(word);
+    }
+
+    let mut word = word.clone();
+    for _ in 0..ITERATION_LIMIT {
+        let applied = apply_once(&word)?;
+        if applied.phones_eq(&word) {
+            return Ok(applied);
+        }
+        word = applied;
+    }
+    Err(RuleError::IterationLimit)
+}
+
+/// Applies a rule to a word a single time; see [`apply_rule`]. `applications`
+/// counts the matches replaced so far.
+fn apply_rule_once(
+    compiled: &CompiledRule,
+    word: &Word,
+    categories: &HashMap<String, Category>,
+    predicates: &Predicates,
+    rng: &mut Rng,
+    applications: &mut usize,
+) -> Result<Word, RuleError> {
+    let rule = compiled.rule;
+    if let Some(guard) = rule.guard {
+        let length = word.phones.iter().filter(|phone| *phone != "#").count();
+        if !guard.allows(length) {
+            return Ok(word.clone());
+        }
+    }
+
+    let mut phones = vec![];
+    let mut index = 0;
+    let mut replaced = vec![];
+
+    let direction = rule.direction;
+    let applicable = applicable_matches(compiled, word, categories, predicates);
+
+    // matches are chosen in the direction they were looked for in, so that
+    // positions and the cap on applications count from there
+    let mut chosen = vec![];
+    for position in 0..applicable.len() {
+        let i = match direction {
+            Direction::LeftToRight => position,
+            Direction::RightToLeft => applicable.len() - 1 - position,
+        };
+        if !at_positions(position, applicable.len(), &rule.target.positions) {
+            continue;
+        }
+        if rule.sporadic.is_some_and(|percent| !rng.chance(percent)) {
+            continue;
+        }
+        if rule
+            .max_applications
+            .is_some_and(|max| *applications >= max)
+        {
+            break;
+        }
+        *applications += 1;
+        chosen.push(i);
+    }
+    chosen.sort_unstable();
+
+    for (found, change) in chosen.into_iter().map(|i| &applicable[i]) {
+        phones.extend_from_slice(&word.phones[index..found.range.start]);
+        let replacement = replacement(&change.pattern, &found.matches, word, categories)?;
+        replaced.push((found.range.clone(), replacement.len()));
+        phones.extend(replacement);
+        index = found.range.end;
+    }
+    phones.extend_from_slice(&word.phones[index..]);
+
+    let syllable_breaks = word
+        .syllable_breaks
+        .iter()
+        .filter_map(|&index| shift_index(index, &replaced))
+        .collect();
+
+    Ok(Word {
+        phones,
+        syllable_breaks,
+        ..word.clone()
+    })
+}
+
+/// The matches of the rule's target which one of its predicates applies to,
+/// each with the change to make there, in the order they were looked for in.
+/// These are the matches positions count, so `@1` is the first match whose
+/// environment holds rather than the first match of the target.
+fn applicable_matches<'r>(
+    compiled: &CompiledRule<'r>,
+    word: &Word,
+    categories: &HashMap<String, Category>,
+    predicates: &Predicates,
+) -> Vec<(TargetMatch, &'r Change)> {
+    let rule = compiled.rule;
+    let targets = rule.target.patterns.len();
+    find_matches(
+        &compiled.targets,
+        word,
+        categories,
+        predicates,
+        rule.direction,
+    )
+    .into_iter()
+    .filter_map(|found| {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("match", range = ?found.range).entered();
+        let predicate = applicable_predicate(compiled, word, &found.range, categories, predicates);
+        predicate
+            .and_then(|p| {
+                let (alternative, range) = (found.alternative, &found.range);
+                applicable_change(p, targets, alternative, word, range, categories, predicates)
+            })
+            .map(|change| (found, change))
+    })
+    .collect()
+}
+
+/// Whether the match at `index` out of `count` is one of the given positions,
+/// where `1` is the first match and `-1` the last. With no positions, every
+/// match is.
+fn at_positions(index: usize, count: usize, positions: &[isize]) -> bool {
+    positions.is_empty()
+        || positions.iter().any(|&position| {
+            if position > 0 {
+                position.unsigned_abs() == index + 1
+            } else {
+                position.unsigned_abs() == count - index
+            }
+        })
+}
+
+/// Finds where the phone at `index` ends up once each of the `replaced` ranges
+/// has been replaced by the given number of phones, or `None` if it was itself
+/// replaced.
+fn shift_index(index: usize, replaced: &[(Range<usize>, usize)]) -> Option<usize> {
+    let mut shifted = index;
+    for (range, len) in replaced {
+        if range.start < index && index < range.end {
+            return None;
+        }
+        if range.start < index {
+            shifted = shifted + len - range.len();
+        }
+    }
+    Some(shifted)
+}
+
+/// Warns about any positions of the rule that are past the number of matches
+/// of its target in the word, counting only those it would apply to with the
+/// given predicates.
+fn check_positions(
+    (rule, rule_index): (&Rule, usize),
+    word: &Word,
+    (categories, predicates): (&HashMap<String, Category>, &Predicates),
+    warnings: &mut Vec<Warning>,
+) {
+    let compiled = CompiledRule::new(rule, &word.graphs, &word.separators);
+    let matches = applicable_matches(&compiled, word, categories, predicates).len();
+    for &position in &rule.target.positions {
+        if position == 0 || position.unsigned_abs() > matches {
+            warnings.push(Warning::PositionOutOfRange {
+                rule: rule_index,
+                word: word.to_string(),
+                position,
+                matches,
+            });
+        }
+    }
+}
+
+/// Whether the pattern matches anywhere in the word.
+fn matches_anywhere(
+    pattern: &Pattern,
+    word: &Word,
+    categories: &HashMap<String, Category>,
+) -> bool {
+    let pattern = CompiledPattern::new(pattern, &word.graphs, &word.separators);
+    let predicates = Predicates::new();
+    (0..=word.phones.len()).any(|index| {
+        word.match_compiled(&pattern, categories, &predicates, index)
+            .is_some()
+    })
+}
+
+/// Warns if the rule's cap on applications stopped it from changing the word
+/// any further than it did, by applying it again without the cap. The
+/// predicates and `rng` should be those the rule was applied with, the latter
+/// as it was before.
+fn check_application_limit(
+    (rule, rule_index): (&Rule, usize),
+    (word, applied): (&Word, &Word),
+    (categories, predicates): (&HashMap<String, Category>, &Predicates),
+    mut rng: Rng,
+    warnings: &mut Vec<Warning>,
+) {
+    let Some(max) = rule.max_applications else {
+        return;
+    };
+    let uncapped = Rule {
+        max_applications: None,
+        ..rule.clone()
+    };
+    let unlimited = apply_rule(&uncapped, word, categories, predicates, &mut rng);
+    if unlimited.map_or(true, |unlimited| !unlimited.phones_eq(applied)) {
+        warnings.push(Warning::ApplicationLimit {
+            rule: rule_index,
+            word: word.to_string(),
+            max,
+        });
+    }
+}
+
+/// Options controlling how rules are applied.
+#[derive(Clone, Debug, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct ApplyOptions {
+    /// Whether to check for likely mistakes while applying rules, recording
+    /// them as warnings in the [`InterpreterState`].
+    pub verbose: bool,
+    /// Whether to apply rules to lowercased words, then capitalise the results
+    /// the same way as the original words, so that lowercase rules also work
+    /// on words like `Apfel`. See [`Casing`] for which capitalisations are kept.
+    pub preserve_case: bool,
+    /// Whether the words are fragments, like affixes, which are parsed without
+    /// word boundaries around them; see [`crate::word::parse_fragment`].
+    pub fragments: bool,
+    /// The seed deciding where sporadic rules apply, so that the same seed
+    /// always gives the same results. Each word decides with a generator of
+    /// its own, seeded from this and where it is among the words.
+    pub seed: u64,
+    /// A pattern which has to match somewhere in a word for the rules to be
+    /// applied to it, like `#[V]` for only vowel-initial words. It's matched
+    /// against the words as given, with the categories defined before the
+    /// first rule. Other words are left as they are.
+    pub filter: Option<Pattern>,
+    /// Whether to find graphs in the words and match patterns against them
+    /// ignoring case, keeping the case of whatever isn't replaced; see
+    /// [`crate::word::parse_case_insensitive`].
+    pub case_insensitive: bool,
+    /// Whether each `.` in the words is a break between syllables, for
+    /// patterns to match with `.`, rather than a phone; see
+    /// [`crate::word::parse_syllabified`].
+    pub syllables: bool,
+    /// The Unicode normalization form to bring the words, graphs and
+    /// separators into before splitting the words into phones. The rules
+    /// themselves are taken as written, so should be in the same form.
+    #[cfg(feature = "unicode-normalization")]
+    pub normalization: crate::word::Normalization,
+}
+
+/// Something suspicious noticed while applying rules, which didn't stop them
+/// from being applied.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Warning {
+    /// A rule asked for a position past the number of matches of its target
+    /// in a word, so that position did nothing.
+    PositionOutOfRange {
+        /// The index of the rule among all the rules.
+        rule: usize,
+        /// The word, as it was before the rule applied.
+        word: String,
+        /// The position asked for.
+        position: isize,
+        /// How many matches there actually were.
+        matches: usize,
+    },
+    /// A rule reached its cap on applications in a word, and would have
+    /// changed it further without it.
+    ApplicationLimit {
+        /// The index of the rule among all the rules.
+        rule: usize,
+        /// The word, as it was before the rule applied.
+        word: String,
+        /// The most times the rule may apply.
+        max: usize,
+    },
+    /// A category edit referred to a category which wasn't defined at that
+    /// point, such as one defined further down, so the reference stood for
+    /// nothing.
+    UndefinedCategory {
+        /// The category being defined or changed.
+        category: String,
+        /// The category referred to.
+        reference: String,
+    },
+}
+
+/// Something which stopped the rules from being applied, along with the rule
+/// and the word it was being applied to. Words themselves can't fail to parse,
+/// since any text splits into phones.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ApplyError {
+    /// A category in a rule's change has a different number of members than
+    /// the category in its target it corresponds to, so the one can't be
+    /// mapped onto the other.
+    CategoryLengthMismatch {
+        /// The index of the rule among all the rules.
+        rule: usize,
+        /// The word, as it was before the rule applied.
+        word: String,
+        /// The number of members of the category in the target, or 0 if there
+        /// was no category there.
+        target: usize,
+        /// The number of members of the category in the change.
+        change: usize,
+    },
+    /// An iterative rule was still changing a word after
+    /// [`ITERATION_LIMIT`] applications, so it likely never stops.
+    IterationLimit {
+        /// The index of the rule among all the rules.
+        rule: usize,
+        /// The word, as it was before the rule applied.
+        word: String,
+    },
+    /// A rule refers to a category which isn't defined at that point, which
+    /// is most likely a typo, since the reference could never match anything.
+    UndefinedCategory {
+        /// The index of the rule among all the rules.
+        rule: usize,
+        /// The word, as it was before the rule applied.
+        word: String,
+        /// The name of the category referred to.
+        category: String,
+    },
+    /// A rule refers to a predicate which isn't registered, such as any used
+    /// through [`apply`], since only the [`crate::engine::Engine`] has them.
+    UndefinedPredicate {
+        /// The index of the rule among all the rules.
+        rule: usize,
+        /// The word, as it was before the rule applied.
+        word: String,
+        /// The name of the predicate referred to.
+        predicate: String,
+    },
+    /// A rule's change has an element which can't say what replaces the
+    /// target, such as a wildcard. The parser rejects these, so this only
+    /// happens with rules built some other way.
+    Unreplaceable {
+        /// The index of the rule among all the rules.
+        rule: usize,
+        /// The word, as it was before the rule applied.
+        word: String,
+        /// The element, as it would be written.
+        element: String,
+    },
+}
+
+impl ApplyError {
+    /// The index of the rule which couldn't be applied, among all the rules.
+    #[must_use]
+    pub fn rule(&self) -> usize {
+        match self {
+            ApplyError::CategoryLengthMismatch { rule, .. }
+            | ApplyError::IterationLimit { rule, .. }
+            | ApplyError::UndefinedCategory { rule, .. }
+            | ApplyError::UndefinedPredicate { rule, .. }
+            | ApplyError::Unreplaceable { rule, .. } => *rule,
+        }
+    }
+
+    /// The word the rule couldn't be applied to, as it was before the rule.
+    #[must_use]
+    pub fn word(&self) -> &str {
+        match self {
+            ApplyError::CategoryLengthMismatch { word, .. }
+            | ApplyError::IterationLimit { word, .. }
+            | ApplyError::UndefinedCategory { word, .. }
+            | ApplyError::UndefinedPredicate { word, .. }
+            | ApplyError::Unreplaceable { word, .. } => word,
+        }
+    }
+
+    /// The source byte range of the rule in the syntax tree it came from, for
+    /// pointing it out in the source.
+    #[must_use]
+    pub fn span(&self, ast: &AST) -> Option<Range<usize>> {
+        rule_sources(ast).get(self.rule()).cloned()
+    }
+}
+
+impl Display for ApplyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApplyError::CategoryLengthMismatch {
+                rule,
+                word,
+                target,
+                change,
+            } => write!(
+                f,
+                "rule {rule} maps a category of {target} members onto one of {change} members, \
+                 in the word `{word}`"
+            ),
+            ApplyError::IterationLimit { rule, word } => write!(
+                f,
+                "rule {rule} was still changing the word `{word}` after {ITERATION_LIMIT} \
+                 iterations"
+            ),
+            ApplyError::UndefinedCategory {
+                rule,
+                word,
+                category,
+            } => write!(
+                f,
+                "rule {rule} refers to the undefined category `{category}`, in the word `{word}`"
+            ),
+            ApplyError::UndefinedPredicate {
+                rule,
+                word,
+                predicate,
+            } => write!(
+                f,
+                "rule {rule} refers to the unregistered predicate `{predicate}`, in the word \
+                 `{word}`"
+            ),
+            ApplyError::Unreplaceable {
+                rule,
+                word,
+                element,
+            } => write!(
+                f,
+                "rule {rule} can't replace its target with `{element}`, in the word `{word}`"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ApplyError {}
+
 /// Applies the rules found in the given syntax tree to a set of words,
-/// parsing the words using the given graphs and separator.
+/// parsing the words using the given graphs and separators.
 ///
 /// ## Returns
 /// The transformed words.
+///
+/// ## Errors
+/// Errors if a rule can't be applied; see [`ApplyError`].
 pub fn apply(
     ast: AST,
     words: Vec<String>,
     graphs: Vec<String>,
-    separator: String,
-) -> Result<(Vec<String>, InterpreterState), ()> {
-    let parsed_words: Vec<_> = words
-        .iter()
-        .map(|word| crate::word::parse(word, graphs.clone(), separator.clone()))
-        .collect();
+    separators: Vec<String>,
+) -> Result<(Vec<String>, InterpreterState), ApplyError> {
+    apply_with_options(ast, words, graphs, separators, &ApplyOptions::default())
+}
 
-    let state = ast.elements.into_iter().map(|(element, _)| element).fold(
-        InterpreterState::default(),
-        |mut state, element| {
-            use ASTElement::*;
-            use CategoryEditKind::*;
-            println!("{state:?}");
-            match element {
-                Rule(rule) => state,
-                CatEdit(edit) => {
-                    let name = edit.target;
-                    let mut elements =
-                        cat_or_els_to_els(edit.elements, &state.categories, &graphs, &separator);
-                    match edit.kind {
-                        Def => {
-                            let category = Category { elements };
-
-                            state.categories.insert(name, category);
-                        }
-                        Add => {
-                            if let Some(category) = state.categories.get(&name) {
-                                let mut category = category.clone();
-                                category.elements.append(&mut elements);
-                                state.categories.insert(name, category);
-                            }
-                        }
-                        Sub => {
-                            if let Some(category) = state.categories.get(&name) {
-                                let mut category = category.clone();
-
-                                category.elements = without(category.elements, elements);
-
-                                state.categories.insert(name, category);
-                            }
-                        }
-                    };
-
-                    state
-                }
-            }
-        },
-    );
+/// Applies the rules found in the given syntax tree to a set of words, like
+/// [`apply`], but with the given options.
+///
+/// ## Returns
+/// The transformed words.
+///
+/// ## Errors
+/// Errors if a rule can't be applied; see [`ApplyError`].
+pub fn apply_with_options(
+    ast: AST,
+    words: Vec<String>,
+    graphs: Vec<String>,
+    separators: Vec<String>,
+    options: &ApplyOptions,
+) -> Result<(Vec<String>, InterpreterState), ApplyError> {
+    let state = InterpreterState::new(graphs, separators);
+    apply_observed(ast, words, state, options, &mut |_| {})
+}
 
-    Ok((vec![], state))
+/// Applies the rules like [`apply`], but starting from the given state rather
+/// than one without categories, such as one made by [`inventory`]. The words
+/// are parsed using the graphs and separators of the state.
+///
+/// ## Errors
+/// Errors if a rule can't be applied; see [`ApplyError`].
+pub fn apply_from_state(
+    ast: AST,
+    words: Vec<String>,
+    state: InterpreterState,
+) -> Result<(Vec<String>, InterpreterState), ApplyError> {
+    apply_observed(ast, words, state, &ApplyOptions::default(), &mut |_| {})
 }
 
-#[cfg(test)]
-mod apply_tests {
-    use std::collections::HashMap;
+/// Applies the rules found in the given syntax tree to words one at a time, as
+/// they're taken from `words`, so that long word lists don't have to be held
+/// in memory all at once. Otherwise, this is like [`apply_with_options`], and
+/// gives the same words.
+///
+/// The category edits are gone through up front, and each word then goes
+/// through the rules on its own. Any warnings the options ask for are dropped,
+/// as there's no [`InterpreterState`] to record them in.
+///
+/// ## Returns
+/// The transformed words, in order, or the error a rule gave for each word.
+pub fn apply_stream(
+    ast: AST,
+    words: impl Iterator<Item = String>,
+    graphs: Vec<String>,
+    separators: Vec<String>,
+    options: &ApplyOptions,
+) -> impl Iterator<Item = Result<String, ApplyError>> {
+    let pipeline = Pipeline::new(ast, InterpreterState::new(graphs, separators), options);
+    words.enumerate().map(move |(index, word)| {
+        let applied = pipeline.apply_word(index, &word, &Predicates::new())?;
+        Ok(pipeline.output(&applied, &word))
+    })
+}
 
-    use super::{apply, Category};
-    use crate::parse::ast;
-    use chumsky::Parser;
-    #[test]
-    fn cat_basic() {
-        let ast = ast().parse("A = b,c,d").into_output().unwrap();
-        let (_, state) = apply(ast, vec!["a".to_string()], vec![], "'".to_string()).unwrap();
+/// Applies the rules found in the given syntax tree to the words in parallel,
+/// giving the transformed words in the same order. Otherwise, this is like
+/// [`apply_with_options`], and gives the same words, though any warnings the
+/// options ask for are dropped.
+///
+/// ## Errors
+/// Errors if a rule can't be applied; see [`ApplyError`].
+#[cfg(feature = "rayon")]
+pub fn apply_parallel(
+    ast: AST,
+    words: &[String],
+    graphs: Vec<String>,
+    separators: Vec<String>,
+    options: &ApplyOptions,
+) -> Result<Vec<String>, ApplyError> {
+    use rayon::prelude::*;
 
-        assert_eq!(
-            state.categories.get("A"),
-            Some(&Category {
-                elements: vec![
-                    vec!["b".to_string()],
-                    vec!["c".to_string()],
-                    vec!["d".to_string()]
-                ]
-            })
+    let pipeline = Pipeline::new(ast, InterpreterState::new(graphs, separators), options);
+    words
+        .par_iter()
+        .enumerate()
+        .map(|(index, word)| {
+            let applied = pipeline.apply_word(index, word, &Predicates::new())?;
+            Ok(pipeline.output(&applied, word))
+        })
+        .collect()
+}
+
+/// A rule, with the categories as they are right before it.
+struct StagedRule {
+    rule: Rule,
+    /// The index of the rule among the rules.
+    index: usize,
+    categories: Arc<HashMap<String, Category>>,
+}
+
+/// An element of a syntax tree which the words go through, once the category
+/// edits between them have been gone through.
+enum Staged {
+    Rule(StagedRule),
+    Checkpoint(String),
+    Stage(String),
+}
+
+/// The rules of a syntax tree, ready to apply to words, with the category
+/// edits gone through up front. Each word goes through the rules on its own,
+/// deciding where sporadic rules apply with a generator of its own, so that it
+/// comes out the same whether the words are gone through together, one at a
+/// time or in parallel.
+struct Pipeline {
+    elements: Vec<Staged>,
+    /// The state once all the category edits have been gone through, with the
+    /// graphs and separators to parse words with.
+    state: InterpreterState,
+    options: ApplyOptions,
+}
+
+impl Pipeline {
+    fn new(ast: AST, state: InterpreterState, options: &ApplyOptions) -> Self {
+        let (graphs, separators) = declared_phonology(&ast, state.graphs, state.separators);
+        #[cfg(feature = "unicode-normalization")]
+        let (graphs, separators) = (
+            options.normalization.normalize_all(&graphs),
+            options.normalization.normalize_all(&separators),
         );
+        let mut state = InterpreterState {
+            graphs,
+            separators,
+            ..state
+        };
+
+        let mut categories = Arc::new(state.categories.clone());
+        let mut elements = vec![];
+        let mut index = 0;
+        for (element, _) in ast.elements {
+            match element {
+                ASTElement::Rule(rule) => {
+                    let categories = Arc::clone(&categories);
+                    elements.push(Staged::Rule(StagedRule {
+                        rule,
+                        index,
+                        categories,
+                    }));
+                    index += 1;
+                }
+                ASTElement::CatEdit(edit) => {
+                    edit_category(&mut state, edit);
+                    categories = Arc::new(state.categories.clone());
+                }
+                ASTElement::Checkpoint(name) => elements.push(Staged::Checkpoint(name)),
+                ASTElement::Stage(name) => elements.push(Staged::Stage(name)),
+                // already taken into account before parsing the words
+                ASTElement::Graphs(_) | ASTElement::Separator(_) | ASTElement::Trace(_) => {}
+            }
+        }
+        Pipeline {
+            elements,
+            state,
+            options: options.clone(),
+        }
+    }
+
+    /// Parses a word to apply the rules to, as the options ask for.
+    fn parse(&self, word: &str) -> Word {
+        let options = &self.options;
+        #[cfg(feature = "unicode-normalization")]
+        let word = &options.normalization.normalize(word);
+        let word = if options.preserve_case {
+            &word.to_lowercase()
+        } else {
+            word
+        };
+        crate::word::parse_with(
+            word,
+            self.state.graphs.clone(),
+            self.state.separators.clone(),
+            !options.fragments,
+            options.case_insensitive,
+            options.syllables,
+        )
+    }
+
+    /// Whether the rules apply to the word, as decided by the filter, which is
+    /// matched with the categories as they are before the first rule.
+    fn selected(&self, word: &Word) -> bool {
+        let categories = self
+            .elements
+            .iter()
+            .find_map(|element| match element {
+                Staged::Rule(rule) => Some(&*rule.categories),
+                _ => None,
+            })
+            .unwrap_or(&self.state.categories);
+        let filter = self.options.filter.as_ref();
+        filter.is_none_or(|filter| matches_anywhere(filter, word, categories))
+    }
+
+    /// Writes out a transformed word, capitalised like the word it came from
+    /// if the options ask for it.
+    fn output(&self, word: &Word, original: &str) -> String {
+        if self.options.preserve_case {
+            Casing::of(original).apply(&word.to_string())
+        } else {
+            word.to_string()
+        }
+    }
+
+    /// Applies a single rule to a word, checking for likely mistakes if the
+    /// options ask for it.
+    fn apply_rule(
+        &self,
+        staged: &StagedRule,
+        word: &Word,
+        predicates: &Predicates,
+        rng: &mut Rng,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<Word, ApplyError> {
+        let rule = (&staged.rule, staged.index);
+        let categories = (&*staged.categories, predicates);
+        if self.options.verbose {
+            check_positions(rule, word, categories, warnings);
+        }
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("rule", index = staged.index, word = %word).entered();
+        let rng_before = *rng;
+        let applied = apply_rule(&staged.rule, word, &staged.categories, predicates, rng)
+            .map_err(|e| e.in_rule(staged.index, word))?;
+        if self.options.verbose {
+            let words = (word, &applied);
+            check_application_limit(rule, words, categories, rng_before, warnings);
+        }
+        Ok(applied)
+    }
+
+    /// Applies all the rules to the word at `index` among the words, dropping
+    /// any warnings.
+    fn apply_word(
+        &self,
+        index: usize,
+        word: &str,
+        predicates: &Predicates,
+    ) -> Result<Word, ApplyError> {
+        let mut word = self.parse(word);
+        if !self.selected(&word) {
+            return Ok(word);
+        }
+        let mut rng = word_rng(self.options.seed, index);
+        for element in &self.elements {
+            if let Staged::Rule(rule) = element {
+                word = self.apply_rule(rule, &word, predicates, &mut rng, &mut vec![])?;
+            }
+        }
+        Ok(word)
+    }
+}
+
+/// The generator deciding where sporadic rules apply in the word at `index`
+/// among the words, so that it doesn't depend on how the words are gone
+/// through. The first word's is seeded with the seed itself.
+fn word_rng(seed: u64, index: usize) -> Rng {
+    Rng::new(seed ^ (index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+}
+
+/// Applies the rules found in the given syntax tree to the words, collecting
+/// every distinct phone found in any of the transformed words, not counting
+/// word boundaries. [`phones_outside`] then finds any of them which aren't in
+/// an inventory, catching rules which emit phones they shouldn't.
+///
+/// The words are transformed as [`apply`] does, but one at a time.
+///
+/// ## Errors
+/// Errors if a rule can't be applied; see [`ApplyError`].
+pub fn output_phones(
+    ast: AST,
+    words: &[String],
+    graphs: Vec<String>,
+    separators: Vec<String>,
+) -> Result<BTreeSet<String>, ApplyError> {
+    let state = InterpreterState::new(graphs, separators);
+    let pipeline = Pipeline::new(ast, state, &ApplyOptions::default());
+    let predicates = Predicates::new();
+    let mut phones = BTreeSet::new();
+    for (index, word) in words.iter().enumerate() {
+        let word = pipeline.apply_word(index, word, &predicates)?;
+        phones.extend(word.phones.into_iter().filter(|phone| phone != "#"));
+    }
+    Ok(phones)
+}
+
+/// The phones which aren't in the given inventory, such as those found by
+/// [`output_phones`] which a language shouldn't have.
+#[must_use]
+pub fn phones_outside(phones: &BTreeSet<String>, inventory: &[String]) -> BTreeSet<String> {
+    phones
+        .iter()
+        .filter(|phone| !inventory.contains(phone))
+        .cloned()
+        .collect()
+}
+
+/// Defines the categories of a standalone inventory file, as parsed by
+/// [`crate::parse::categories`], giving a state to apply rules from.
+#[must_use]
+pub fn inventory(
+    edits: Vec<CategoryEdit>,
+    graphs: Vec<String>,
+    separators: Vec<String>,
+) -> InterpreterState {
+    let mut state = InterpreterState::new(graphs, separators);
+    for edit in edits {
+        edit_category(&mut state, edit);
+    }
+    state
+}
+
+/// Counts how many of the words each rule changes, in the order of the rules.
+/// Rules which never change anything count 0, and are likely dead.
+///
+/// ## Errors
+/// Errors if a rule can't be applied; see [`ApplyError`].
+pub fn rule_coverage(
+    ast: &AST,
+    words: &[String],
+    graphs: Vec<String>,
+    separators: Vec<String>,
+) -> Result<Vec<usize>, ApplyError> {
+    let mut coverage = vec![0; ast.rule_spans().len()];
+    apply_observed(
+        ast.clone(),
+        words.to_vec(),
+        InterpreterState::new(graphs, separators),
+        &ApplyOptions::default(),
+        &mut |event| {
+            if let Event::Rule {
+                index,
+                before,
+                after,
+                ..
+            } = event
+            {
+                if !before.phones_eq(after) {
+                    coverage[index] += 1;
+                }
+            }
+        },
+    )?;
+    Ok(coverage)
+}
+
+/// The words as they were at a `checkpoint` directive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub name: String,
+    pub words: Vec<String>,
+}
+
+/// Applies the rules like [`apply`], also recording the words at each
+/// `checkpoint "name"` directive, in order. This gives the staged forms of the
+/// words without a full trace of every rule.
+///
+/// ## Errors
+/// Errors if a rule can't be applied; see [`ApplyError`].
+pub fn apply_with_checkpoints(
+    ast: AST,
+    words: Vec<String>,
+    graphs: Vec<String>,
+    separators: Vec<String>,
+) -> Result<(Vec<String>, Vec<Checkpoint>), ApplyError> {
+    let mut checkpoints = vec![];
+    let (words, _) = apply_observed(
+        ast,
+        words,
+        InterpreterState::new(graphs, separators),
+        &ApplyOptions::default(),
+        &mut |event| {
+            if let Event::Checkpoint { name, words } = event {
+                checkpoints.push(Checkpoint {
+                    name: name.to_string(),
+                    words,
+                });
+            }
+        },
+    )?;
+    Ok((words, checkpoints))
+}
+
+/// The words as they were at the end of a `stage:`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stage {
+    pub name: String,
+    pub words: Vec<String>,
+}
+
+/// Applies the rules like [`apply`], recording the words at the end of each
+/// stage, which starts at a `stage: name` directive and lasts until the next
+/// one. Each rule is applied to every word before the next, so each stage is
+/// done with all of the words before the next one starts, and its words are
+/// the lexicon at that layer of its history. Rules before the first stage are
+/// applied, but aren't a stage of their own.
+///
+/// ## Errors
+/// Errors if a rule can't be applied; see [`ApplyError`].
+pub fn apply_stages(
+    ast: AST,
+    words: Vec<String>,
+    graphs: Vec<String>,
+    separators: Vec<String>,
+) -> Result<Vec<Stage>, ApplyError> {
+    let mut stages: Vec<Stage> = vec![];
+    let mut current: Option<String> = None;
+    let (words, _) = apply_observed(
+        ast,
+        words,
+        InterpreterState::new(graphs, separators),
+        &ApplyOptions::default(),
+        &mut |event| {
+            if let Event::Stage { name, words } = event {
+                // a stage ends where the next one starts
+                if let Some(name) = current.replace(name.to_string()) {
+                    stages.push(Stage { name, words });
+                }
+            }
+        },
+    )?;
+    if let Some(name) = current {
+        stages.push(Stage { name, words });
+    }
+    Ok(stages)
+}
+
+/// How a word changed, rule by rule.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Derivation {
+    /// The word as given.
+    pub word: String,
+    /// Each rule which changed the word, in order.
+    pub steps: Vec<DerivationStep>,
+    /// The word after all the rules.
+    pub output: String,
+}
+
+/// A rule which changed a word, on the way to its output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DerivationStep {
+    /// The index of the rule among all the rules.
+    pub rule: usize,
+    /// The source byte range of the rule, so that it can be pointed out.
+    pub span: Range<usize>,
+    pub before: String,
+    pub after: String,
+}
+
+/// The source byte range of each rule, by the rule's index.
+fn rule_sources(ast: &AST) -> Vec<Range<usize>> {
+    ast.rule_spans().into_iter().map(|(_, span)| span).collect()
+}
+
+/// Applies the rules like [`apply`], recording how each of the words changed
+/// on the way to its output, in the order the words are given. See
+/// [`apply_traced`] to only record the derivations of a few words.
+///
+/// ## Errors
+/// Errors if a rule can't be applied; see [`ApplyError`].
+pub fn apply_trace(
+    ast: AST,
+    words: Vec<String>,
+    graphs: Vec<String>,
+    separators: Vec<String>,
+) -> Result<Vec<Derivation>, ApplyError> {
+    let spans = rule_sources(&ast);
+    let mut derivations: Vec<_> = words
+        .iter()
+        .map(|word| Derivation {
+            word: word.clone(),
+            steps: vec![],
+            output: String::new(),
+        })
+        .collect();
+    let (outputs, _) = apply_observed(
+        ast,
+        words,
+        InterpreterState::new(graphs, separators),
+        &ApplyOptions::default(),
+        &mut |event| {
+            if let Event::Rule {
+                word,
+                index,
+                before,
+                after,
+            } = event
+            {
+                if !before.phones_eq(after) {
+                    derivations[word].steps.push(DerivationStep {
+                        rule: index,
+                        span: spans[index].clone(),
+                        before: before.to_string(),
+                        after: after.to_string(),
+                    });
+                }
+            }
+        },
+    )?;
+    for (derivation, output) in derivations.iter_mut().zip(outputs) {
+        derivation.output = output;
+    }
+    Ok(derivations)
+}
+
+/// Applies the rules like [`apply`], also recording the derivations of the
+/// words listed in `trace: word, ...` directives, in the order the words are
+/// given. This narrows a large run down to the few forms being looked into.
+///
+/// ## Errors
+/// Errors if a rule can't be applied; see [`ApplyError`].
+pub fn apply_traced(
+    ast: AST,
+    words: Vec<String>,
+    graphs: Vec<String>,
+    separators: Vec<String>,
+) -> Result<(Vec<String>, Vec<Derivation>), ApplyError> {
+    let traced = traced_words(&ast);
+    let spans = rule_sources(&ast);
+    let mut derivations: Vec<_> = words
+        .iter()
+        .enumerate()
+        .filter(|(_, word)| traced.contains(word))
+        .map(|(index, word)| {
+            let derivation = Derivation {
+                word: word.clone(),
+                steps: vec![],
+                output: String::new(),
+            };
+            (index, derivation)
+        })
+        .collect();
+    let (words, _) = apply_observed(
+        ast,
+        words,
+        InterpreterState::new(graphs, separators),
+        &ApplyOptions::default(),
+        &mut |event| {
+            if let Event::Trace {
+                word,
+                rule,
+                before,
+                after,
+            } = event
+            {
+                if let Some((_, derivation)) = derivations.iter_mut().find(|(i, _)| *i == word) {
+                    derivation.steps.push(DerivationStep {
+                        rule,
+                        span: spans[rule].clone(),
+                        before: before.to_string(),
+                        after: after.to_string(),
+                    });
+                }
+            }
+        },
+    )?;
+    let derivations = derivations
+        .into_iter()
+        .map(|(index, derivation)| Derivation {
+            output: words[index].clone(),
+            ..derivation
+        })
+        .collect();
+    Ok((words, derivations))
+}
+
+/// The words listed in `trace:` directives, wherever they are.
+fn traced_words(ast: &AST) -> Vec<String> {
+    ast.elements
+        .iter()
+        .filter_map(|(element, _)| match element {
+            ASTElement::Trace(words) => Some(words.clone()),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+/// Something which happened while applying rules, as seen by
+/// [`apply_observed`].
+enum Event<'a> {
+    /// A rule was applied to a word.
+    Rule {
+        /// The index of the word among the words.
+        word: usize,
+        /// The index of the rule among the rules.
+        index: usize,
+        before: &'a Word,
+        after: &'a Word,
+    },
+    /// A rule changed a word listed in a `trace:` directive.
+    Trace {
+        /// The index of the word among the words.
+        word: usize,
+        /// The index of the rule among the rules.
+        rule: usize,
+        before: &'a Word,
+        after: &'a Word,
+    },
+    /// A checkpoint was reached, with the words as they were there.
+    Checkpoint { name: &'a str, words: Vec<String> },
+    /// A stage started, with the words as they were before it.
+    Stage { name: &'a str, words: Vec<String> },
+}
+
+/// The graphs and separators to parse words with, as given or as overridden by
+/// directives in the rules, wherever they are.
+pub(crate) fn declared_phonology(
+    ast: &AST,
+    mut graphs: Vec<String>,
+    mut separators: Vec<String>,
+) -> (Vec<String>, Vec<String>) {
+    for (element, _) in &ast.elements {
+        match element {
+            ASTElement::Graphs(declared) => graphs.clone_from(declared),
+            ASTElement::Separator(declared) => separators.clone_from(declared),
+            _ => {}
+        }
+    }
+    (graphs, separators)
+}
+
+/// Defines or changes a category, using the graphs and separators of the state
+/// to split its members into phones.
+///
+/// References to categories which aren't defined yet are warned about, and
+/// stand for nothing until they're defined. Once they are, the category is
+/// resolved again from its edits, as are any categories referring to it, and
+/// the warnings are taken back. So `B = [A]` before `A = x, y` gives `B` the
+/// members `x` and `y` from then on, though rules before `A` is defined see
+/// `B` without them.
+pub(crate) fn edit_category(state: &mut InterpreterState, edit: CategoryEdit) {
+    let mut undefined = vec![];
+    undefined_references(&edit.elements, &state.categories, &mut undefined);
+    for reference in &undefined {
+        state.warnings.push(Warning::UndefinedCategory {
+            category: edit.target.clone(),
+            reference: reference.clone(),
+        });
+    }
+
+    let name = edit.target.clone();
+    let kind = edit.kind.clone();
+    let pending = state
+        .pending
+        .iter()
+        .position(|(pending, _)| *pending == name);
+    match pending {
+        Some(i) if kind == CategoryEditKind::Def => {
+            state.pending.remove(i);
+        }
+        Some(i) => state.pending[i].1.edits.push(edit.clone()),
+        None => {}
+    }
+    let waiting = !undefined.is_empty() || refers_to_pending(&edit.elements, &state.pending);
+    let exists = state.categories.contains_key(&name) || kind == CategoryEditKind::Def;
+    if !state.pending.iter().any(|(pending, _)| *pending == name) && waiting && exists {
+        let base = match kind {
+            CategoryEditKind::Def => vec![],
+            _ => state.categories[&name].elements.clone(),
+        };
+        state.pending.push((
+            name.clone(),
+            PendingCategory {
+                base,
+                edits: vec![edit.clone()],
+            },
+        ));
+    }
+
+    let category = state
+        .categories
+        .get(&name)
+        .map(|category| category.elements.clone());
+    if let Some(elements) = edited(category, edit, state) {
+        state.categories.insert(name, Category { elements });
+    }
+    if kind == CategoryEditKind::Def {
+        resolve_pending(state);
+    }
+}
+
+/// The members of a category once edited, given its members before, or `None`
+/// if it isn't defined and the edit doesn't define it.
+fn edited(
+    elements: Option<Vec<Vec<String>>>,
+    edit: CategoryEdit,
+    state: &InterpreterState,
+) -> Option<Vec<Vec<String>>> {
+    use CategoryEditKind::*;
+
+    let mut members = cat_or_els_to_els(
+        edit.elements,
+        &state.categories,
+        &state.graphs,
+        &state.separators,
+    );
+    match (edit.kind, elements) {
+        (Def, _) => Some(members),
+        (Add, Some(mut elements)) => {
+            elements.append(&mut members);
+            Some(elements)
+        }
+        (Sub, Some(elements)) => Some(without(elements, &members)),
+        (Add | Sub, None) => None,
+    }
+}
+
+/// Whether `elements` refer to any of the pending categories.
+fn refers_to_pending(elements: &[CatOrEl], pending: &[(String, PendingCategory)]) -> bool {
+    // every reference is undefined among no categories at all
+    let mut references = vec![];
+    undefined_references(elements, &HashMap::new(), &mut references);
+    references
+        .iter()
+        .any(|reference| pending.iter().any(|(name, _)| name == reference))
+}
+
+/// Resolves each pending category again from its edits, now that another
+/// category has been defined, until none of them change. Those no longer
+/// referring to anything undefined or pending stop being pending, and the
+/// warnings about references which are now defined are taken back.
+fn resolve_pending(state: &mut InterpreterState) {
+    let resolved: Vec<_> = state.pending.iter().map(|(name, _)| name.clone()).collect();
+    for _ in 0..=state.pending.len() {
+        let mut changed = false;
+        for (name, pending) in &state.pending {
+            let mut elements = Some(pending.base.clone());
+            for edit in &pending.edits {
+                elements = edited(elements, edit.clone(), state);
+            }
+            let elements = elements.unwrap_or_default();
+            if state.categories[name].elements != elements {
+                changed = true;
+                state.categories.insert(name.clone(), Category { elements });
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    loop {
+        let settled = state.pending.iter().position(|(_, pending)| {
+            pending.edits.iter().all(|edit| {
+                let mut undefined = vec![];
+                undefined_references(&edit.elements, &state.categories, &mut undefined);
+                undefined.is_empty() && !refers_to_pending(&edit.elements, &state.pending)
+            })
+        });
+        let Some(settled) = settled else {
+            break;
+        };
+        state.pending.remove(settled);
+    }
+
+    let categories = &state.categories;
+    state.warnings.retain(|warning| {
+        !matches!(warning, Warning::UndefinedCategory { category, reference }
+            if resolved.contains(category) && categories.contains_key(reference))
+    });
+}
+
+/// Applies the rules like [`apply_with_options`], telling `observe` about each
+/// rule applied to each word and each checkpoint reached.
+///
+/// Each rule is applied to every word before the next, so that the words are
+/// all at the same point at each checkpoint and stage, though each goes
+/// through the rules just as it would on its own; see [`Pipeline`].
+#[allow(clippy::needless_pass_by_value)]
+fn apply_observed(
+    ast: AST,
+    words: Vec<String>,
+    state: InterpreterState,
+    options: &ApplyOptions,
+    observe: &mut dyn FnMut(Event),
+) -> Result<(Vec<String>, InterpreterState), ApplyError> {
+    let traced_words = traced_words(&ast);
+    let traced: Vec<_> = words.iter().map(|w| traced_words.contains(w)).collect();
+    let pipeline = Pipeline::new(ast, state, options);
+    // only the engine registers predicates
+    let predicates = Predicates::new();
+
+    let mut parsed_words: Vec<_> = words.iter().map(|word| pipeline.parse(word)).collect();
+    let selected: Vec<_> = parsed_words
+        .iter()
+        .map(|word| pipeline.selected(word))
+        .collect();
+    let mut rngs: Vec<_> = (0..words.len())
+        .map(|index| word_rng(options.seed, index))
+        .collect();
+    let output = |parsed_words: &[Word]| -> Vec<String> {
+        parsed_words
+            .iter()
+            .zip(&words)
+            .map(|(word, original)| pipeline.output(word, original))
+            .collect()
+    };
+
+    let mut warnings = vec![];
+    for element in &pipeline.elements {
+        match element {
+            Staged::Rule(rule) => {
+                for (i, word) in parsed_words
+                    .iter_mut()
+                    .enumerate()
+                    .filter(|(i, _)| selected[*i])
+                {
+                    let rng = &mut rngs[i];
+                    let applied =
+                        pipeline.apply_rule(rule, word, &predicates, rng, &mut warnings)?;
+                    observe(Event::Rule {
+                        word: i,
+                        index: rule.index,
+                        before: word,
+                        after: &applied,
+                    });
+                    if traced[i] && !word.phones_eq(&applied) {
+                        observe(Event::Trace {
+                            word: i,
+                            rule: rule.index,
+                            before: word,
+                            after: &applied,
+                        });
+                    }
+                    *word = applied;
+                }
+            }
+            Staged::Checkpoint(name) => observe(Event::Checkpoint {
+                name,
+                words: output(&parsed_words),
+            }),
+            Staged::Stage(name) => observe(Event::Stage {
+                name,
+                words: output(&parsed_words),
+            }),
+        }
+    }
+
+    let words = output(&parsed_words);
+    let mut state = pipeline.state;
+    state.warnings.append(&mut warnings);
+    Ok((words, state))
+}
+
+#[cfg(test)]
+mod apply_tests {
+    use super::{
+        apply, apply_from_state, apply_rule, apply_stages, apply_stream, apply_trace, apply_traced,
+        apply_with_checkpoints, apply_with_options, inventory, rule_coverage, ApplyError,
+        ApplyOptions, Category, Checkpoint, Derivation, DerivationStep, Stage, Warning,
+    };
+    use crate::{
+        common::Rng,
+        parse::{ast, PatternElement},
+    };
+    use chumsky::Parser;
+    use std::collections::{BTreeSet, HashMap};
+
+    /// Asserts that applying `rules` to `word` gives `expected`.
+    fn assert_applies(rules: &str, word: &str, expected: &str) {
+        let ast = ast().parse(rules).into_output().unwrap();
+        let (words, _) = apply(ast, vec![word.to_string()], vec![], vec!["'".to_string()]).unwrap();
+        assert_eq!(words, vec![expected.to_string()], "{rules} on {word}");
+    }
+
+    #[test]
+    fn cat_basic() {
+        let ast = ast().parse("A = b,c,d").into_output().unwrap();
+        let (_, state) = apply(ast, vec!["a".to_string()], vec![], vec!["'".to_string()]).unwrap();
+
+        assert_eq!(
+            state.categories.get("A"),
+            Some(&Category {
+                elements: vec![
+                    vec!["b".to_string()],
+                    vec!["c".to_string()],
+                    vec!["d".to_string()]
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn substitution() {
+        let ast = ast().parse("a > b").into_output().unwrap();
+        let (words, _) =
+            apply(ast, vec!["abc".to_string()], vec![], vec!["'".to_string()]).unwrap();
+
+        assert_eq!(words, vec!["bbc".to_string()]);
+    }
+
+    #[test]
+    fn substitution_order() {
+        let ast = ast().parse("a > b\nb > c\nxy > z").into_output().unwrap();
+        let words = vec!["abc".to_string(), "xyxy".to_string()];
+        let (words, _) = apply(ast, words, vec![], vec!["'".to_string()]).unwrap();
+
+        assert_eq!(words, vec!["ccc".to_string(), "zz".to_string()]);
+    }
+
+    #[test]
+    fn negative_lookahead() {
+        // in the target, the lookahead only blocks the `a` directly before a `b`
+        let ast = ast().parse("a(?!b) > x").into_output().unwrap();
+        let words = vec!["abac".to_string(), "aab".to_string()];
+        let (words, _) = apply(ast, words, vec![], vec!["'".to_string()]).unwrap();
+
+        assert_eq!(words, vec!["abxc".to_string(), "xab".to_string()]);
+
+        // in an environment it's only part of what has to hold, so the `a`
+        // before the `d` is left alone too, while the exception only blocks
+        // the one before the `b`
+        let cases = [
+            ("a > x / _(?!b)c", "abacad", "abxcad"),
+            ("a > x ! _b", "abacad", "abxcxd"),
+            ("a > x / (?<!b)_c", "bacad", "bacad"),
+            ("a > x / (?<!b)_c", "bacac", "bacxc"),
+            ("a > x ! b_", "bacad", "bacxd"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+    }
+
+    #[test]
+    fn position_out_of_range() {
+        let source = "a@1|3 > b";
+        let words = vec!["aba".to_string(), "aaa".to_string()];
+        let options = ApplyOptions {
+            verbose: true,
+            ..ApplyOptions::default()
+        };
+
+        let parsed = ast().parse(source).into_output().unwrap();
+        let (_, state) = apply_with_options(
+            parsed,
+            words.clone(),
+            vec![],
+            vec!["'".to_string()],
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(
+            state.warnings,
+            vec![Warning::PositionOutOfRange {
+                rule: 0,
+                word: "aba".to_string(),
+                position: 3,
+                matches: 2,
+            }]
+        );
+
+        // and only when asked for
+        let parsed = ast().parse(source).into_output().unwrap();
+        let (_, state) = apply(parsed, words, vec![], vec!["'".to_string()]).unwrap();
+        assert!(state.warnings.is_empty());
+
+        // counting only the matches in the environment
+        let parsed = ast().parse("a@2 > b / _c").into_output().unwrap();
+        let words = vec!["acab".to_string()];
+        let (_, state) =
+            apply_with_options(parsed, words, vec![], vec!["'".to_string()], &options).unwrap();
+        assert_eq!(
+            state.warnings,
+            vec![Warning::PositionOutOfRange {
+                rule: 0,
+                word: "acab".to_string(),
+                position: 2,
+                matches: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn checks_with_predicates() {
+        let mut predicates = super::Predicates::new();
+        predicates.insert(String::from("vowel"), Box::new(|phone| phone == "a"));
+        let rule = || crate::parse::Rule::builder().target("{:vowel}").change("x");
+        let mut warnings = vec![];
+        let categories = HashMap::new();
+
+        let word = crate::word::parse("kata", vec![], vec![]);
+        let positions = rule().position(3).build().unwrap();
+        super::check_positions(
+            (&positions, 0),
+            &word,
+            (&categories, &predicates),
+            &mut warnings,
+        );
+        assert_eq!(
+            warnings,
+            vec![Warning::PositionOutOfRange {
+                rule: 0,
+                word: "kata".to_string(),
+                position: 3,
+                matches: 2,
+            }]
+        );
+
+        // the only vowel is replaced either way, so the cap changed nothing
+        warnings.clear();
+        let word = crate::word::parse("kat", vec![], vec![]);
+        let capped = rule().max_applications(1).build().unwrap();
+        let applied = apply_rule(&capped, &word, &categories, &predicates, &mut Rng::new(0));
+        let words = (&word, &applied.ok().unwrap());
+        let (rule, categories) = ((&capped, 0), (&categories, &predicates));
+        super::check_application_limit(rule, words, categories, Rng::new(0), &mut warnings);
+        assert_eq!(warnings, vec![]);
+    }
+
+    #[test]
+    fn add_category_reference() {
+        let parsed = ast()
+            .parse("C = p\nR = w, r\nC += [R], h\nC += [M]\nC += [L]\nL = l")
+            .into_output()
+            .unwrap();
+        let (_, state) = apply(parsed, vec![], vec![], vec!["'".to_string()]).unwrap();
+
+        assert_eq!(
+            state.categories["C"].elements,
+            vec![vec!["p"], vec!["w"], vec!["r"], vec!["h"], vec!["l"]]
+        );
+        assert_eq!(
+            state.warnings,
+            vec![Warning::UndefinedCategory {
+                category: "C".to_string(),
+                reference: "M".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn forward_category_reference() {
+        let source = "B = [A], z\nC = [B], w\nD = x, y, z\nD -= [B]\nA = x, y";
+        let parsed = ast().parse(source).into_output().unwrap();
+        let (_, state) = apply(parsed, vec![], vec![], vec!["'".to_string()]).unwrap();
+
+        assert_eq!(
+            state.categories["B"].elements,
+            vec![vec!["x"], vec!["y"], vec!["z"]]
+        );
+        assert_eq!(
+            state.categories["C"].elements,
+            vec![vec!["x"], vec!["y"], vec!["z"], vec!["w"]]
+        );
+        assert!(state.categories["D"].elements.is_empty());
+        assert!(state.warnings.is_empty(), "{:?}", state.warnings);
+
+        // rules before the definition see the category without it
+        let source = "B = [A]\n[B] > o\nA = a\n[B] > e";
+        let parsed = ast().parse(source).into_output().unwrap();
+        let (words, _) = apply(parsed, vec![String::from("ta")], vec![], vec![]).unwrap();
+        assert_eq!(words, vec!["te"]);
+    }
+
+    #[test]
+    fn environment() {
+        let parsed = ast().parse("a > e / _i").into_output().unwrap();
+        let words = vec!["ai".to_string(), "ab".to_string(), "aia".to_string()];
+        let (words, _) = apply(parsed, words, vec![], vec!["'".to_string()]).unwrap();
+
+        assert_eq!(
+            words,
+            vec!["ei".to_string(), "ab".to_string(), "eia".to_string()]
+        );
+    }
+
+    #[test]
+    fn environment_sides() {
+        let cases = [
+            ("a > e / _b", "abac", "ebac"),
+            ("a > e / c_", "abca", "abce"),
+            ("a > e / c_b", "cabcac", "cebcac"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+    }
+
+    #[test]
+    fn environment_alternatives() {
+        let parsed = ast().parse("a > e / _i, b_").into_output().unwrap();
+        let words = vec!["ai".to_string(), "ba".to_string(), "ca".to_string()];
+        let (words, _) = apply(parsed, words, vec![], vec!["'".to_string()]).unwrap();
+
+        assert_eq!(
+            words,
+            vec!["ei".to_string(), "be".to_string(), "ca".to_string()]
+        );
+    }
+
+    #[test]
+    fn environment_conjunction() {
+        for rules in ["a > b / _c & d_", "a > b / _c&d_"] {
+            let parsed = ast().parse(rules).into_output().unwrap();
+            let words = ["dac", "dab", "eac", "dacdac"].map(String::from).to_vec();
+            let (words, _) = apply(parsed, words, vec![], vec!["'".to_string()]).unwrap();
+
+            assert_eq!(words, vec!["dbc", "dab", "eac", "dbcdbc"], "{rules}");
+        }
+    }
+
+    #[test]
+    fn epenthesis() {
+        let cases = [
+            ("+ x / a_b", "ab", "axb"),
+            ("+ x / a_b", "ba", "ba"),
+            ("+ x / #_", "ab", "xab"),
+            ("+ x / _#", "ab", "abx"),
+        ];
+
+        for (source, word, expected) in cases {
+            assert_applies(source, word, expected);
+        }
+    }
+
+    #[test]
+    fn deletion() {
+        let parsed = ast().parse("- a / _b").into_output().unwrap();
+        let (words, _) = apply(
+            parsed,
+            vec!["abac".to_string()],
+            vec![],
+            vec!["'".to_string()],
+        )
+        .unwrap();
+        assert_eq!(words, vec!["bac".to_string()]);
+    }
+
+    #[test]
+    fn metathesis() {
+        let cases = [
+            ("~ sp", "wasp", "waps"),
+            ("~ sk / _#", "asks", "asks"),
+            ("~ sk / _#", "ask", "aks"),
+            ("V = a, e\nC = r, d\n~ [V][C] / #_", "erda", "reda"),
+            ("~ skt", "askta", "atksa"),
+            // `~` is still a phone where the rule isn't a metathesis
+            ("~ > n", "a~", "an"),
+        ];
+        for (source, word, expected) in cases {
+            let parsed = ast().parse(source).into_output().unwrap();
+            let (words, _) = apply(parsed, vec![word.to_string()], vec![], vec![]).unwrap();
+            assert_eq!(words, vec![expected.to_string()], "{source} on {word}");
+        }
+    }
+
+    #[test]
+    fn table() {
+        let words = vec!["abcab".to_string()];
+
+        let table = ast().parse("table: a:b b:c").into_output().unwrap();
+        let rules = ast().parse("a > b\nb > c").into_output().unwrap();
+
+        assert_eq!(
+            apply(table, words.clone(), vec![], vec!["'".to_string()])
+                .unwrap()
+                .0,
+            apply(rules, words, vec![], vec!["'".to_string()])
+                .unwrap()
+                .0,
+        );
+    }
+
+    #[test]
+    fn syllable_boundary() {
+        // syllables are given by separating them with `.` in the input
+        let options = ApplyOptions {
+            syllables: true,
+            ..ApplyOptions::default()
+        };
+        let apply_syllabified = |rules: &str, words: &[&str]| {
+            let ast = ast().parse(rules).into_output().unwrap();
+            let words = words.iter().map(ToString::to_string).collect();
+            apply_with_options(ast, words, vec![], vec!["'".to_string()], &options)
+                .unwrap()
+                .0
+        };
+        let words = apply_syllabified("a > á / _.", &["ta.kan.ta", "tan.ka"]);
+        assert_eq!(words, vec!["tá.kan.tá", "tan.ká"]);
+        // a break isn't a phone to replace
+        assert_eq!(apply_syllabified("\\. > x", &["ta.ka"]), vec!["ta.ka"]);
+
+        // otherwise `.` is a phone like any other, written `\.` in rules
+        assert_applies("\\. > x", "a.b", "axb");
+        assert_applies("a > e", "a.b", "e.b");
+        // where only the word boundary breaks a syllable
+        assert_applies("a > á / _.", "ta.ka", "ta.ká");
+        assert_applies("a > á / _\\.", "ta.ka", "tá.ka");
+    }
+
+    #[test]
+    fn exception() {
+        let cases = [
+            ("ē > æ ! _#", "tēdē", "tædē"),
+            // the exception wins over the environment
+            ("a > e / _b ! _b#", "abab", "ebab"),
+            ("+ x / b_ ! _#", "bab", "bxab"),
+            ("a > e / _b ! c_, _c", "cabab", "cabeb"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+    }
+
+    #[test]
+    fn positions() {
+        let cases = [
+            ("a@2 > b", "aaa", "aba"),
+            ("a@1|3 > b", "aaa", "bab"),
+            ("a@4 > b", "aaa", "aaa"),
+            ("a@2|5 > b", "aaa", "aba"),
+            ("a@2 > e", "ababa", "abeba"),
+            ("a@-1 > e", "ababa", "ababe"),
+            ("a@-1 > b", "aaa", "aab"),
+            ("a@-2 > b", "aaa", "aba"),
+            ("a@-1 > b", "bab", "bbb"),
+            ("a@2|-1 > b", "aaaa", "abab"),
+            ("a@-4 > b", "aaa", "aaa"),
+            ("a@1|-1 > e", "ababa", "ebabe"),
+            ("+ x@2 / _b", "abcbdb", "abcxbdb"),
+            ("+ x@-1 / _b", "abcbdb", "abcbdxb"),
+            ("- a@-1 / _b", "abcabab", "abcabb"),
+            ("- a@1 / _b", "acabab", "acbab"),
+            // only the matches in the environment count
+            ("a@1 > b / _c", "abacac", "abbcac"),
+            ("a@-1 > e / _c ! b_", "acacbac", "acecbac"),
+            ("a@1 > b / _c", "abab", "abab"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+    }
+
+    #[test]
+    fn rule_group() {
+        let parsed = ast().parse("{ a > b; c > d } / _#").into_output().unwrap();
+        let words = ["ac", "ca", "aca"].map(String::from).to_vec();
+        let (words, _) = apply(parsed, words, vec![], vec!["'".to_string()]).unwrap();
+
+        assert_eq!(words, vec!["ad", "cb", "acb"]);
+    }
+
+    #[test]
+    fn repeat_count() {
+        let cases = [
+            ("a{*} > a{#-1}", "aaaa", "aaa"),
+            ("a{*} > a{#-1}", "baaaab", "baaab"),
+            ("a{*} > a{#-1}", "bab", "bb"),
+            ("a{*}b > b{#}", "aaab", "bbb"),
+            ("a{*} > a{#+1}", "ab", "aab"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+    }
+
+    #[test]
+    fn gemination() {
+        let categories = "C = p, t, k, s, l\nV = a, e, i, o, u\n";
+        let cases = [
+            // gemination, doubling a consonant between vowels
+            ("[C] > %{2} / [V]_[V]", "akeli", "akkelli"),
+            ("[C] > %% / [V]_[V]", "akeli", "akkelli"),
+            ("l > l{2} / _#", "sal", "sall"),
+            // degemination, where `"` matches a repeat of the phone before it
+            ("- \" / [C]_", "akkelli", "akeli"),
+            ("[C]\" > [C]", "sappo tukka", "sapo tuka"),
+            ("s{2} > s", "assa issa", "asa isa"),
+            // vowels aren't consonants
+            ("- \" / [C]_", "saak", "saak"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(&format!("{categories}{rules}"), word, expected);
+        }
+    }
+
+    #[test]
+    fn preserve_case() {
+        let parsed = ast().parse("a > e\np > b").into_output().unwrap();
+        let words = ["Apfel", "APFEL", "apfel"].map(String::from).to_vec();
+        let options = ApplyOptions {
+            preserve_case: true,
+            ..ApplyOptions::default()
+        };
+        let (words, _) =
+            apply_with_options(parsed, words, vec![], vec!["'".to_string()], &options).unwrap();
+
+        assert_eq!(words, vec!["Ebfel", "EBFEL", "ebfel"]);
+    }
+
+    #[test]
+    fn target_reference() {
+        let cases = [
+            ("a > x%x", "bab", "bxaxb"),
+            ("a > %%", "bab", "baab"),
+            ("ab > %c%", "abd", "abcabd"),
+            ("+ %", "ab", "ab"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+    }
+
+    #[test]
+    fn target_reversed() {
+        let cases = [
+            ("ab > <", "cabd", "cbad"),
+            ("abc > <", "abcd", "cbad"),
+            ("ab > <%", "ab", "baab"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+
+        // outside a change there's no target to reverse
+        let errors = crate::parse("a > b / _<").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "`<` stands for the target, so it can only be used in a change"
+        );
+    }
+
+    #[test]
+    fn change_exception() {
+        let cases = [
+            ("a > e!_#, o", "aba", "ebo"),
+            ("a > e!_#!#_, o", "aaa", "oeo"),
+            // with no other change to fall back on, a blocked match is left alone
+            ("a > e!_#", "aba", "eba"),
+            ("a > e!_#, o / b_", "abaa", "abea"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+    }
+
+    #[test]
+    fn ditto_change() {
+        let cases = [
+            ("a > \"x", "a", "ax"),
+            ("ab > x\"", "ab", "xb"),
+            ("ab > \"\"\"", "ab", "abb"),
+            ("j > \"j / [C]_", "tja", "tjja"),
+            ("+ \" / a_", "ab", "ab"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(&format!("C = t,k\n{rules}"), word, expected);
+        }
+    }
+
+    #[test]
+    fn category_mapping() {
+        let cases = [
+            ("[a,e] > [x,y]", "abe", "xby"),
+            ("V = a,e\nW = x,y\n[V] > [W]", "eba", "ybx"),
+            ("V = a,e\nb[V] > [V]b", "bea", "eba"),
+            ("[a,e][a,e] > [x,y][o,u]", "ea", "yo"),
+            ("[a,[]]b > [x,y]b", "abb", "xbyb"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+    }
+
+    #[test]
+    fn category_mapping_mismatch() {
+        let ast = ast().parse("b > c\n[a,e] > [x,y,z]").into_output().unwrap();
+        let result = apply(ast, vec!["a".to_string()], vec![], vec!["'".to_string()]);
+
+        assert_eq!(
+            result.unwrap_err(),
+            ApplyError::CategoryLengthMismatch {
+                rule: 1,
+                word: "a".to_string(),
+                target: 2,
+                change: 3
+            }
+        );
+    }
+
+    #[test]
+    fn guard() {
+        let parsed = ast().parse("a > e if len <= 3").into_output().unwrap();
+        let words = ["ta", "tak", "taka", "ta ka"].map(String::from).to_vec();
+        let (words, _) = apply(parsed, words, vec![], vec!["'".to_string()]).unwrap();
+
+        assert_eq!(words, vec!["te", "tek", "taka", "ta ka"]);
+    }
+
+    #[test]
+    fn built_rule() {
+        use crate::parse::{ASTElement, Pattern, PatternElement::*, Rule, AST};
+        use chumsky::span::SimpleSpan;
+
+        let pattern = |elements| Pattern { elements };
+        let rules = [
+            Rule::builder()
+                .target("a")
+                .change("e")
+                .environment("_b")
+                .build()
+                .unwrap(),
+            Rule::builder()
+                .target_pattern(pattern(vec![Text(String::from("a"))]))
+                .change_pattern(pattern(vec![Text(String::from("e"))]))
+                .environment_pattern(pattern(vec![Gap, Text(String::from("b"))]))
+                .build()
+                .unwrap(),
+        ];
+        let parsed = ast().parse("a > e / _b").into_output().unwrap();
+        let words = vec!["abac".to_string()];
+        let (parsed, _) = apply(parsed, words.clone(), vec![], vec!["'".to_string()]).unwrap();
+        assert_eq!(parsed, vec!["ebac"]);
+
+        for rule in rules {
+            let built = AST {
+                elements: vec![(ASTElement::Rule(rule), SimpleSpan::new(0, 0))],
+            };
+            let (built, _) = apply(built, words.clone(), vec![], vec!["'".to_string()]).unwrap();
+            assert_eq!(built, parsed);
+        }
+    }
+
+    #[test]
+    fn multiple_targets() {
+        let cases = [
+            ("a, b > x, y", "ab", "xy"),
+            ("a, e, o > ɔ, i, u", "aeo", "ɔiu"),
+            // a single change is shared by every target
+            ("a, b > x", "abc", "xxc"),
+            ("a, b > x, y / _c", "acbcab", "xcycab"),
+            ("a, e > x!_#, y", "aea", "xya"),
+            ("- a, b / _c", "acbcab", "ccab"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+    }
+
+    #[test]
+    fn null_category_member() {
+        let parsed = ast().parse("V = a,e,[]\nt[V]k > x").into_output().unwrap();
+        let words = ["tak", "tk", "tik"].map(String::from).to_vec();
+        let (words, _) = apply(parsed, words, vec![], vec!["'".to_string()]).unwrap();
+
+        assert_eq!(words, vec!["x", "x", "tik"]);
+    }
+
+    #[test]
+    fn coverage() {
+        let parsed = ast()
+            .parse("a > e\nV = e,i\nx > y\n[V] > o / _#")
+            .into_output()
+            .unwrap();
+        let words = ["ta", "tat", "ti"].map(String::from);
+        let coverage = rule_coverage(&parsed, &words, vec![], vec!["'".to_string()]).unwrap();
+
+        assert_eq!(coverage, vec![2, 0, 2]);
+    }
+
+    #[test]
+    fn graphs_directive() {
+        let words = vec!["atsha".to_string()];
+
+        let parsed = ast().parse("h > x").into_output().unwrap();
+        let (without, _) = apply(parsed, words.clone(), vec![], vec!["'".to_string()]).unwrap();
+        assert_eq!(without, vec!["atsxa"]);
+
+        let parsed = ast()
+            .parse("graphs: sh, ts, tsh\nh > x")
+            .into_output()
+            .unwrap();
+        let (with, state) = apply(parsed, words, vec![], vec!["'".to_string()]).unwrap();
+        assert_eq!(with, vec!["atsha"]);
+        assert_eq!(state.graphs, vec!["sh", "ts", "tsh"]);
+    }
+
+    #[test]
+    fn checkpoints() {
+        let source = "a > e\ncheckpoint \"Old\"\nk > h\ncheckpoint \"Middle\"\ne > i";
+        let parsed = ast().parse(source).into_output().unwrap();
+        let words = ["kat", "tak"].map(String::from).to_vec();
+        let (words, checkpoints) =
+            apply_with_checkpoints(parsed, words, vec![], vec!["'".to_string()]).unwrap();
+
+        assert_eq!(words, vec!["hit", "tih"]);
+        assert_eq!(
+            checkpoints,
+            vec![
+                Checkpoint {
+                    name: String::from("Old"),
+                    words: vec![String::from("ket"), String::from("tek")],
+                },
+                Checkpoint {
+                    name: String::from("Middle"),
+                    words: vec![String::from("het"), String::from("teh")],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn stages() {
+        let source = "a > e\nstage: west-germanic\nk > h\ne > i\nstage: ingvaeonic\nh > x";
+        let parsed = ast().parse(source).into_output().unwrap();
+        let words = ["kat", "tak"].map(String::from).to_vec();
+        let stages = apply_stages(parsed, words, vec![], vec!["'".to_string()]).unwrap();
+
+        assert_eq!(
+            stages,
+            vec![
+                Stage {
+                    name: String::from("west-germanic"),
+                    words: vec![String::from("hit"), String::from("tih")],
+                },
+                Stage {
+                    name: String::from("ingvaeonic"),
+                    words: vec![String::from("xit"), String::from("tix")],
+                },
+            ]
+        );
+        assert_ne!(stages[0].words, stages[1].words);
+    }
+
+    #[test]
+    fn escaped_brackets() {
+        let cases = [
+            ("[a,\\]] > x", "b]a", "bxx"),
+            ("[\\,,\\[] > x", "a,[", "axx"),
+            ("P = \\], \\,\n[P] > x", "a],b", "axxb"),
+            ("P = \\], \\,\nQ = [P], c\n[Q] > x", "a],c", "axxx"),
+            ("\\] = a, b\n[\\]] > x", "ab]", "xx]"),
+        ];
+
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+    }
+
+    #[test]
+    fn negated_category() {
+        let cases = [
+            ("[!a] > x", "ba", "xa"),
+            ("[!a] > x", "a", "a"),
+            ("[!a,b] > x / _#", "kab", "kab"),
+            ("V = a, e\n[![V]] > x", "kate", "xaxe"),
+            ("k > g / [!a]_", "akok", "akog"),
+        ];
+
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+    }
+
+    #[test]
+    fn sporadic() {
+        let apply_seeded = |rules: &str, seed| {
+            let parsed = ast().parse(rules).into_output().unwrap();
+            let options = ApplyOptions {
+                seed,
+                ..ApplyOptions::default()
+            };
+            let words = vec![String::from("aaaa")];
+            apply_with_options(parsed, words, vec![], vec!["'".to_string()], &options)
+                .unwrap()
+                .0
+        };
+
+        assert_eq!(apply_seeded("a > e ?", 0), vec!["eeae"]);
+        assert_eq!(apply_seeded("a > e ?", 0), apply_seeded("a > e ?", 0));
+        assert_eq!(apply_seeded("a > e ?100%", 7), vec!["eeee"]);
+        assert_eq!(apply_seeded("a > e ?0%", 7), vec!["aaaa"]);
+
+        // each word decides on its own, whatever other words there are
+        let parsed = ast().parse("a > e ?").into_output().unwrap();
+        let words = vec![String::from("aaaa"), String::from("aaaa")];
+        let (words, _) = apply(parsed, words, vec![], vec!["'".to_string()]).unwrap();
+        assert_eq!(words[0], "eeae");
+    }
+
+    #[test]
+    fn length_mark() {
+        // `:` is its own phone, following the vowel it lengthens
+        let cases = [
+            ("ī, ē, ā, ō, ū > i:, e:, ɑ:, o:, u:", "sēþ bōk", "se:þ bo:k"),
+            ("ē > æ: ! _#", "wēgē", "wæ:gē"),
+            ("ai, au > æ:, ā", "haus stain", "hās stæ:n"),
+            ("e: > i", "se:þ", "siþ"),
+            ("- : / e_", "se:þ ta:", "seþ ta:"),
+        ];
+
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+    }
+
+    #[test]
+    fn iterative() {
+        let cases = [
+            ("aa > a repeat", "aaaa", "a"),
+            ("aa > a", "aaaa", "aa"),
+            ("ab > b / _b repeat", "aaabb", "bb"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+
+        let ast = ast()
+            .parse("x > y\na, b > b, a repeat")
+            .into_output()
+            .unwrap();
+        let result = apply(ast, vec![String::from("ab")], vec![], vec!["'".to_string()]);
+        assert_eq!(
+            result.unwrap_err(),
+            ApplyError::IterationLimit {
+                rule: 1,
+                word: "ab".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn right_to_left() {
+        let cases = [
+            ("aa > b", "aaa", "ba"),
+            ("aa rtl > b", "aaa", "ab"),
+            ("aXa > Y", "aXaXa", "YXa"),
+            ("aXa rtl > Y", "aXaXa", "aXY"),
+            ("a@1 rtl > e", "aba", "abe"),
+            ("a@-1 rtl > e", "aba", "eba"),
+            ("+ x rtl / _#", "ab", "abx"),
+            ("- a@1 rtl", "aba", "ab"),
+            // a match running into the one after it backtracks to a shorter one
+            ("ab(a) > x", "ababa", "xba"),
+            ("ab(a) rtl > x", "ababa", "xx"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+    }
+
+    #[test]
+    fn optional_length() {
+        let rules = "V = a, e, i, o, u\nm,b,d,g > w̃,w,ð,ɣ / [V](:)_[[V],ă]";
+        let cases = [
+            ("aba", "awa"),
+            ("a:ga", "a:ɣa"),
+            ("o:dă", "o:ðă"),
+            ("ima", "iw̃a"),
+            ("abka", "abka"),
+            ("ab", "ab"),
+            ("::ba", "::ba"),
+        ];
+        for (word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+    }
+
+    #[test]
+    fn inventory_file() {
+        let edits = crate::parse::categories()
+            .parse("V = a, e\nC = p, t, sh\n// more vowels\nV += o\n")
+            .into_output()
+            .unwrap();
+        let state = inventory(edits, vec![String::from("sh")], vec!["'".to_string()]);
+
+        assert_eq!(
+            state.categories["V"].elements,
+            vec![vec!["a"], vec!["e"], vec!["o"]]
+        );
+
+        let parsed = ast().parse("[V] > i / [C]_").into_output().unwrap();
+        let words = vec![String::from("shota"), String::from("kat")];
+        let (words, state) = apply_from_state(parsed, words, state).unwrap();
+
+        assert_eq!(words, vec!["shiti", "kat"]);
+        assert!(state.categories.contains_key("C"));
+    }
+
+    #[test]
+    fn category_subtraction() {
+        let cases = [
+            ("V = a, e, i\n[V-a] > o", "tatiet", "tatoot"),
+            ("V = a, e, i\n[V-a,e] > o", "tatiet", "tatoet"),
+            ("V = a, e, i\nF = e, i\n[V-F] > o", "tatiet", "totiet"),
+            ("V = a, e, i\nW = [V-i], o\n[W] > u", "tatiot", "tutiut"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+
+        let parsed = ast().parse("V = a, e, i\nW = V-a").into_output().unwrap();
+        let (_, state) = apply(parsed, vec![], vec![], vec!["'".to_string()]).unwrap();
+        assert_eq!(state.categories["W"].elements, vec![vec!["e"], vec!["i"]]);
+    }
+
+    #[test]
+    fn category_intersection() {
+        let definitions = "V = a, e, i\nF = i, o, e\n";
+        let cases = [
+            ("X = V&F", vec![vec!["e"], vec!["i"]]),
+            ("X = F&V", vec![vec!["i"], vec!["e"]]),
+            ("X = V&i, e, u", vec![vec!["e"], vec!["i"]]),
+            ("X = [V&F], [V-F]", vec![vec!["e"], vec!["i"], vec!["a"]]),
+            ("X = V&u", vec![]),
+        ];
+        for (edit, expected) in cases {
+            let source = format!("{definitions}{edit}");
+            let parsed = ast().parse(&source).into_output().unwrap();
+            let (_, state) = apply(parsed, vec![], vec![], vec!["'".to_string()]).unwrap();
+            assert_eq!(state.categories["X"].elements, expected, "{edit}");
+        }
+
+        let source = format!("{definitions}[V&F] > u");
+        let parsed = ast().parse(&source).into_output().unwrap();
+        let (words, _) = apply(
+            parsed,
+            vec![String::from("taieo")],
+            vec![],
+            vec!["'".to_string()],
+        )
+        .unwrap();
+        assert_eq!(words, vec!["tauuo"]);
+    }
+
+    #[test]
+    fn nested_category_with_literal() {
+        let cases = [
+            ("V = a, e\n[[V],ă] > o", "tăpek", "topok"),
+            ("V = a, e\nt > d / _[[V],ă]", "tătetk", "dădetk"),
+            ("V = a, e\n[[V],ă] > [x,y,z]", "ăea", "zyx"),
+            ("V = a, e\nV += ă\n[[V],ă] > o", "ăi", "oi"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+    }
+
+    #[test]
+    fn fragments() {
+        let options = ApplyOptions {
+            fragments: true,
+            ..ApplyOptions::default()
+        };
+        let cases = [
+            ("t > d / _#", "st", "st"),
+            ("t > d / _#", "st ast", "sd ast"),
+            ("s > z / #_", "st", "st"),
+            ("s > z / t_", "ts", "tz"),
+            ("+ e / _s", "st", "est"),
+        ];
+        for (rules, word, expected) in cases {
+            let parsed = ast().parse(rules).into_output().unwrap();
+            let words = vec![word.to_string()];
+            let (words, _) =
+                apply_with_options(parsed, words, vec![], vec!["'".to_string()], &options).unwrap();
+            assert_eq!(words, vec![expected.to_string()], "{rules} on {word}");
+        }
+    }
+
+    #[test]
+    fn stream() {
+        let rules = "V = a, e\n[V] > i / _t\nV += o\n[V] > u / #_\nt > d / _#";
+        let words = ["kat", "set", "ota", "esot", "o"].map(String::from);
+
+        let (applied, _) = apply(
+            ast().parse(rules).into_output().unwrap(),
+            words.to_vec(),
+            vec![],
+            vec!["'".to_string()],
+        )
+        .unwrap();
+        let streamed = apply_stream(
+            ast().parse(rules).into_output().unwrap(),
+            words.into_iter(),
+            vec![],
+            vec!["'".to_string()],
+            &ApplyOptions::default(),
+        )
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+        assert_eq!(streamed, applied);
+        assert_eq!(streamed, vec!["kid", "sid", "uta", "usod", "u"]);
+
+        // with options, checkpoints, stages and sporadic rules too
+        let rules = "V = a, e, o\ncheckpoint \"start\"\n[V] > i / _t ?50%\nstage: late\n\
+                     trace: Kat\nk > g ?50% max 1";
+        let words = ["Kat", "SET", "ota", "kaket", "sokot"].map(String::from);
+        let options = ApplyOptions {
+            preserve_case: true,
+            seed: 3,
+            filter: Some(crate::parse::pattern().parse("[V]t").into_output().unwrap()),
+            verbose: true,
+            ..ApplyOptions::default()
+        };
+        let parse = || ast().parse(rules).into_output().unwrap();
+        let separators = vec!["'".to_string()];
+        let (applied, _) = apply_with_options(
+            parse(),
+            words.to_vec(),
+            vec![],
+            separators.clone(),
+            &options,
+        )
+        .unwrap();
+        let streamed = apply_stream(parse(), words.into_iter(), vec![], separators, &options)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(streamed, applied);
+    }
+
+    #[test]
+    fn filter() {
+        let options = ApplyOptions {
+            filter: Some(crate::parse::pattern().parse("#[V]").into_output().unwrap()),
+            ..ApplyOptions::default()
+        };
+        let parsed = ast()
+            .parse("V = a, e, o\nk > g\n[V] > i / _#")
+            .into_output()
+            .unwrap();
+        let words = ["akka", "kako", "eko", "ok ka"].map(String::from).to_vec();
+        let (words, _) =
+            apply_with_options(parsed, words, vec![], vec!["'".to_string()], &options).unwrap();
+
+        assert_eq!(words, vec!["aggi", "kako", "egi", "og gi"]);
+    }
+
+    #[test]
+    fn trace() {
+        let parsed = ast()
+            .parse("trace: hund\nu > o\nd > t / _#\nk > h")
+            .into_output()
+            .unwrap();
+        let words = ["hund", "kund", "hand"].map(String::from).to_vec();
+        let (words, derivations) =
+            apply_traced(parsed, words, vec![], vec!["'".to_string()]).unwrap();
+
+        assert_eq!(words, vec!["hont", "hont", "hant"]);
+        assert_eq!(
+            derivations,
+            vec![Derivation {
+                word: "hund".to_string(),
+                steps: vec![
+                    DerivationStep {
+                        rule: 0,
+                        span: 12..17,
+                        before: "hund".to_string(),
+                        after: "hond".to_string(),
+                    },
+                    DerivationStep {
+                        rule: 1,
+                        span: 18..28,
+                        before: "hond".to_string(),
+                        after: "hont".to_string(),
+                    },
+                ],
+                output: "hont".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn trace_all() {
+        let source = "u > o\nV = a, o\nd > t / [V]n_#\nk > h";
+        let parsed = ast().parse(source).into_output().unwrap();
+        let words = ["hund", "kind"].map(String::from).to_vec();
+        let derivations = apply_trace(parsed, words, vec![], vec!["'".to_string()]).unwrap();
+
+        let [hund, kind] = &derivations[..] else {
+            panic!("there should be a derivation for each word");
+        };
+        let steps: Vec<_> = hund.steps.iter().map(|step| &step.after).collect();
+        assert_eq!(steps, ["hond", "hont"]);
+        assert_eq!(&source[hund.steps[1].span.clone()], "d > t / [V]n_#");
+        assert_eq!(hund.output, "hont");
+
+        assert_eq!(kind.steps.len(), 1);
+        assert_eq!(kind.steps[0].rule, 2);
+        assert_eq!(&source[kind.steps[0].span.clone()], "k > h");
+        assert_eq!(kind.output, "hind");
+    }
+
+    #[test]
+    fn overlapping_targets() {
+        let cases = [
+            ("ab, a > x, y", "abc", "xc"),
+            ("a, ab > x, y", "abc", "yc"),
+            ("a, ab > x, y", "acb", "xcb"),
+            // as long, so the first given wins
+            ("ab, ac, a > x, y, z", "acab", "yx"),
+            ("a, b > x, y", "ab", "xy"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+    }
+
+    #[test]
+    fn undefined_category() {
+        let cases = [
+            ("[X] > a", "X"),
+            ("V = a\na > [V] / _[X]", "X"),
+            ("V = a\n[V] > e\nb > [W, V-[Y]]", "Y"),
+            ("a > b ! (c[Z])_", "Z"),
+        ];
+        for (rules, category) in cases {
+            let parsed = ast().parse(rules).into_output().unwrap();
+            let error = apply(parsed, vec!["ab".to_string()], vec![], vec![]).unwrap_err();
+            assert!(
+                matches!(&error, ApplyError::UndefinedCategory { category: c, .. } if c == category),
+                "{rules}: {error:?}"
+            );
+        }
+
+        let parsed = ast().parse("[V] > e\nV = a").into_output().unwrap();
+        let error = apply(parsed, vec!["ab".to_string()], vec![], vec![]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "rule 0 refers to the undefined category `V`, in the word `ab`"
+        );
+    }
+
+    #[test]
+    fn undefined_predicate() {
+        for rules in [
+            "{:vowel} > a",
+            "a > b / _(c{:vowel})",
+            "a > b ! (?!{:vowel})_",
+        ] {
+            let parsed = ast().parse(rules).into_output().unwrap();
+            let error = apply(parsed, vec!["ab".to_string()], vec![], vec![]).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "rule 0 refers to the unregistered predicate `vowel`, in the word `ab`",
+                "{rules}"
+            );
+        }
+    }
+
+    #[test]
+    fn error_context() {
+        let source = "V = a, e\nb > c\n[V] > [x, y, z] / _c";
+        let parsed = ast().parse(source).into_output().unwrap();
+        let words = vec!["ta".to_string(), "tab".to_string()];
+        let error = apply(parsed.clone(), words, vec![], vec![]).unwrap_err();
+
+        assert_eq!(error.rule(), 1);
+        assert_eq!(error.word(), "tac");
+        let span = error.span(&parsed).unwrap();
+        assert_eq!(&source[span], "[V] > [x, y, z] / _c");
+        assert_eq!(
+            error.to_string(),
+            "rule 1 maps a category of 2 members onto one of 3 members, in the word `tac`"
+        );
+    }
+
+    #[test]
+    fn unreplaceable_change() {
+        let mut rule = crate::parse::Rule::builder()
+            .target("a")
+            .change("b")
+            .build()
+            .unwrap();
+        rule.predicates[0].change[0].pattern.elements[0] =
+            PatternElement::Wildcard(crate::common::Wildcard::Greedy);
+        let word = crate::word::parse("cat", vec![], vec![]);
+        let (categories, predicates) = (HashMap::new(), HashMap::new());
+        let error = apply_rule(&rule, &word, &categories, &predicates, &mut Rng::new(0))
+            .map_err(|e| e.in_rule(0, &word))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "rule 0 can't replace its target with `*`, in the word `cat`"
+        );
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn normalization() {
+        use crate::word::Normalization;
+
+        // precomposed in the rule, decomposed in the word
+        let rules = "\u{113} > i";
+        let words = vec!["ke\u{304}t".to_string()];
+        for (normalization, expected) in [
+            (Normalization::None, "ke\u{304}t"),
+            (Normalization::Nfc, "kit"),
+        ] {
+            let options = ApplyOptions {
+                normalization,
+                ..ApplyOptions::default()
+            };
+            let parsed = ast().parse(rules).into_output().unwrap();
+            let separators = vec!["'".to_string()];
+            let (words, _) =
+                apply_with_options(parsed, words.clone(), vec![], separators, &options).unwrap();
+            assert_eq!(words, vec![expected.to_string()], "{normalization:?}");
+        }
+    }
+
+    #[test]
+    fn word_boundary() {
+        let cases = [
+            ("a > e / _#", "aba", "abe"),
+            ("a > e / _#", "ab ba", "ab be"),
+            ("a > e / #_", "aba ab", "eba eb"),
+            ("a > e / _#", "ab", "ab"),
+            ("a > e / b_#b", "ba ba", "be ba"),
+            // a boundary in a change splits the word
+            ("- # / a_b", "a b", "ab"),
+            ("ab > a#b", "abc", "a bc"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let cases = [
+            ("a > x", "BAba", "Bxbx"),
+            ("sh > s", "SHasha", "sasa"),
+            ("V = a, e\nW = i, o\n[V] > [W] / _b", "BAbEB", "BiboB"),
+            ("[!a] > x", "AbA", "AxA"),
+            ("a > % / _\"", "bAa", "bAa"),
+        ];
+        for (rules, word, expected) in cases {
+            let options = ApplyOptions {
+                case_insensitive: true,
+                ..ApplyOptions::default()
+            };
+            let parsed = ast().parse(rules).into_output().unwrap();
+            let graphs = vec!["sh".to_string()];
+            let words = vec![word.to_string()];
+            let (words, _) =
+                apply_with_options(parsed, words, graphs, vec!["'".to_string()], &options).unwrap();
+            assert_eq!(words, vec![expected.to_string()], "{rules} on {word}");
+        }
+
+        let parsed = ast().parse("a > x").into_output().unwrap();
+        let (words, _) = apply(parsed, vec!["BAba".to_string()], vec![], vec![]).unwrap();
+        assert_eq!(words, vec!["BAbx"]);
+    }
+
+    #[test]
+    fn category_queries() {
+        let parsed = ast()
+            .parse("N = m, n\nT = p, t\nC = [N], [T], sh\nC -= n")
+            .into_output()
+            .unwrap();
+        let (_, state) = apply(
+            parsed,
+            vec![],
+            vec![String::from("sh")],
+            vec!["'".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(state.category("N"), state.categories.get("N"));
+        assert_eq!(state.category("X"), None);
+        assert_eq!(
+            state.resolve("C").unwrap(),
+            vec![vec!["m"], vec!["p"], vec!["t"], vec!["sh"]]
+        );
+        assert_eq!(state.resolve("X"), None);
+
+        assert!(state.contains("C", &[String::from("sh")]));
+        assert!(state.contains("N", &[String::from("n")]));
+        assert!(!state.contains("C", &[String::from("n")]));
+        assert!(!state.contains("C", &[String::from("s"), String::from("h")]));
+        assert!(!state.contains("X", &[String::from("m")]));
+    }
+
+    #[test]
+    fn output_phones() {
+        let parsed = ast()
+            .parse("graphs: sh\nk > sh / _i\nu > y")
+            .into_output()
+            .unwrap();
+        let words = ["kiku", "ukas"].map(String::from);
+        let phones = super::output_phones(parsed, &words, vec![], vec!["'".to_string()]).unwrap();
+
+        let expected = ["sh", "i", "k", "y", "a", "s"].map(String::from);
+        assert_eq!(phones, BTreeSet::from(expected));
+
+        let inventory = ["sh", "i", "k", "u", "a", "s"].map(String::from);
+        assert_eq!(
+            super::phones_outside(&phones, &inventory),
+            BTreeSet::from([String::from("y")])
+        );
+    }
+
+    #[test]
+    fn max_applications() {
+        let cases = [
+            ("a > e max 2", "banana", "benena"),
+            ("a rtl > e max 2", "banana", "banene"),
+            ("a > e max 2", "ba", "be"),
+            ("a > e / _n max 1", "banana", "benana"),
+            // the cap counts applications over all iterations
+            ("a > aa repeat max 3", "ba", "baaaa"),
+        ];
+        for (rules, word, expected) in cases {
+            assert_applies(rules, word, expected);
+        }
+
+        let options = ApplyOptions {
+            verbose: true,
+            ..ApplyOptions::default()
+        };
+        let parsed = ast().parse("a > e max 2").into_output().unwrap();
+        let words = ["banana", "bana"].map(String::from).to_vec();
+        let (_, state) =
+            apply_with_options(parsed, words, vec![], vec!["'".to_string()], &options).unwrap();
+        assert_eq!(
+            state.warnings,
+            vec![Warning::ApplicationLimit {
+                rule: 0,
+                word: "banana".to_string(),
+                max: 2,
+            }]
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing() {
+        use std::sync::{Arc, Mutex};
+        use tracing::{field, span, Metadata, Subscriber};
+
+        /// Records the names of spans and the messages of events.
+        struct Recorder(Arc<Mutex<Vec<String>>>);
+
+        struct Message(Option<String>);
+
+        impl field::Visit for Message {
+            fn record_debug(&mut self, field: &field::Field, value: &dyn std::fmt::Debug) {
+                if field.name() == "message" {
+                    self.0 = Some(format!("{value:?}"));
+                }
+            }
+        }
+
+        impl Subscriber for Recorder {
+            fn enabled(&self, _: &Metadata) -> bool {
+                true
+            }
+            fn new_span(&self, span: &span::Attributes) -> span::Id {
+                let mut recorded = self.0.lock().unwrap();
+                recorded.push(span.metadata().name().to_string());
+                span::Id::from_u64(recorded.len() as u64)
+            }
+            fn record(&self, _: &span::Id, _: &span::Record) {}
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+            fn event(&self, event: &tracing::Event) {
+                let mut message = Message(None);
+                event.record(&mut message);
+                self.0.lock().unwrap().extend(message.0);
+            }
+            fn enter(&self, _: &span::Id) {}
+            fn exit(&self, _: &span::Id) {}
+        }
+
+        let recorded = Arc::new(Mutex::new(vec![]));
+        tracing::subscriber::with_default(Recorder(Arc::clone(&recorded)), || {
+            let parsed = ast().parse("a > e / _n ! b_").into_output().unwrap();
+            let words = ["banan", "ka"].map(String::from).to_vec();
+            apply(parsed, words, vec![], vec!["'".to_string()]).unwrap();
+        });
+
+        let recorded = recorded.lock().unwrap();
+        for expected in [
+            "rule",
+            "match",
+            "exception blocked the match",
+            "environment blocked the match",
+        ] {
+            assert!(recorded.iter().any(|r| r == expected), "{expected}");
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel() {
+        let rules = "V = a, e\n[V] > i / _t\nV += o\n[V] > u / #_\nt > d / _#";
+        let words = ["kat", "set", "ota", "esot", "o", "tatata tot"]
+            .iter()
+            .cycle()
+            .take(300)
+            .map(|word| String::from(*word))
+            .collect::<Vec<_>>();
+
+        let (applied, _) = apply(
+            ast().parse(rules).into_output().unwrap(),
+            words.clone(),
+            vec![],
+            vec!["'".to_string()],
+        )
+        .unwrap();
+        let parallel = super::apply_parallel(
+            ast().parse(rules).into_output().unwrap(),
+            &words,
+            vec![],
+            vec!["'".to_string()],
+            &super::ApplyOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(parallel, applied);
+
+        // sporadic rules apply the same way in each word either way
+        let options = super::ApplyOptions {
+            seed: 11,
+            ..super::ApplyOptions::default()
+        };
+        let rules = "t > d ?50%\n[] > e / #_ ?30%";
+        let separators = vec!["'".to_string()];
+        let parse = || ast().parse(rules).into_output().unwrap();
+        let (applied, _) =
+            apply_with_options(parse(), words.clone(), vec![], separators.clone(), &options)
+                .unwrap();
+        let parallel =
+            super::apply_parallel(parse(), &words, vec![], separators, &options).unwrap();
+        assert_eq!(parallel, applied);
+    }
+}
+
+#[cfg(test)]
+mod bench {
+    extern crate test;
+    use chumsky::Parser;
+    use test::Bencher;
+
+    use crate::parse::{ast, bench::SAXONISH};
+
+    /// Made-up Proto-Germanic-looking words, as many as asked for.
+    fn words(count: usize) -> Vec<String> {
+        let onsets = ["", "k", "st", "hw", "b", "dr", "g", "sk"];
+        let vowels = ["a", "ē", "i", "ō", "u", "ą", "ai"];
+        let codas = ["", "z", "n", "t", "þ"];
+        (0..count)
+            .map(|i| {
+                let syllable = |n: usize| {
+                    let o = onsets[n % onsets.len()];
+                    let v = vowels[n / onsets.len() % vowels.len()];
+                    format!("{o}{v}")
+                };
+                format!(
+                    "{}{}{}",
+                    syllable(i),
+                    syllable(i / 3 + 1),
+                    codas[i % codas.len()]
+                )
+            })
+            .collect()
+    }
+
+    /// The benchmarks should time the matcher and replacer, so the words have
+    /// to be changed by rules with wildcards, categories and environments,
+    /// rather than the rules failing or never matching.
+    #[test]
+    fn exercises_corpus() {
+        let parsed = ast().parse(SAXONISH).into_output().unwrap();
+        let derivations =
+            super::apply_trace(parsed, words(200), vec![], vec![String::from("'")]).unwrap();
+        let fired: Vec<_> = derivations
+            .iter()
+            .flat_map(|derivation| &derivation.steps)
+            .map(|step| &SAXONISH[step.span.clone()])
+            .collect();
+
+        assert!(fired.iter().any(|rule| rule.contains("{*}")), "{fired:?}");
+        assert!(fired.iter().any(|rule| rule.contains("[C]")), "{fired:?}");
+        assert!(fired.iter().any(|rule| rule.contains(" / ")), "{fired:?}");
+    }
+
+    #[bench]
+    fn apply_serial(b: &mut Bencher) {
+        let parsed = ast().parse(SAXONISH).into_output().unwrap();
+        let words = words(200);
+        b.iter(|| {
+            super::apply(
+                parsed.clone(),
+                words.clone(),
+                vec![],
+                vec![String::from("'")],
+            )
+        });
+    }
+
+    #[cfg(feature = "rayon")]
+    #[bench]
+    fn apply_parallel(b: &mut Bencher) {
+        let parsed = ast().parse(SAXONISH).into_output().unwrap();
+        let words = words(200);
+        let options = super::ApplyOptions::default();
+        let separators = vec![String::from("'")];
+        b.iter(|| {
+            super::apply_parallel(parsed.clone(), &words, vec![], separators.clone(), &options)
+        });
     }
 }
@@ -0,0 +1,321 @@
+//! Builders for rules and category edits, for generating sound changes in code
+//! rather than writing them out in a file. Each part is given as source, like
+//! `_#` for an environment, and parsed as it would be in a file; any errors
+//! are returned when the rule or edit is built. Rule parts can also be given
+//! as already built [`Pattern`]s, which are taken as they are.
+
+use chumsky::prelude::*;
+
+use crate::{
+    parse::{
+        cat_or_els, change, environment_group, pattern, text, CategoryEdit, CategoryEditKind,
+        Change, Direction, EnvironmentGroup, Guard, Pattern, Predicate, Rule, E,
+    },
+    report::ParseError,
+};
+
+/// Parses the whole of `source` with `parser`, recording any errors.
+fn parse_part<'src, T>(
+    parser: impl Parser<'src, &'src str, T, E<'src>>,
+    source: &'src str,
+    errors: &mut Vec<ParseError>,
+) -> Option<T> {
+    let (output, errs) = parser.then_ignore(end()).parse(source).into_output_errors();
+    errors.extend(errs.iter().map(|error| ParseError::new(source, error)));
+    output.filter(|_| errs.is_empty())
+}
+
+/// Builds a [`Rule`] with a single predicate, e.g.
+/// `RuleBuilder::new().target("a").change("b").environment("_c").build()` for
+/// `a > b / _c`.
+#[derive(Debug, Clone, Default)]
+pub struct RuleBuilder {
+    rule: Rule,
+    predicate: Predicate,
+    errors: Vec<ParseError>,
+}
+
+impl RuleBuilder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a target pattern, as an alternative to any added before it.
+    #[must_use]
+    pub fn target(mut self, source: &str) -> Self {
+        if let Some(pattern) = parse_part(pattern(), source, &mut self.errors) {
+            self.rule.target.patterns.push(pattern);
+        }
+        self
+    }
+
+    /// Adds a target pattern, like [`RuleBuilder::target`] but without
+    /// parsing it.
+    #[must_use]
+    pub fn target_pattern(mut self, pattern: Pattern) -> Self {
+        self.rule.target.patterns.push(pattern);
+        self
+    }
+
+    /// Adds a position of the target the rule applies to; see
+    /// [`crate::parse::Target::positions`].
+    #[must_use]
+    pub fn position(mut self, position: isize) -> Self {
+        self.rule.target.positions.push(position);
+        self
+    }
+
+    /// Adds a change, with any exceptions of its own, like `e!_#`. Like in the
+    /// parser, with several targets each is paired with the change added in
+    /// the same place, and with one target only the first change which isn't
+    /// blocked by its own exceptions applies.
+    #[must_use]
+    pub fn change(mut self, source: &str) -> Self {
+        if let Some(change) = parse_part(change(), source, &mut self.errors) {
+            self.predicate.change.push(change);
+        }
+        self
+    }
+
+    /// Adds a change without any exceptions of its own, like
+    /// [`RuleBuilder::change`] but without parsing it.
+    #[must_use]
+    pub fn change_pattern(mut self, pattern: Pattern) -> Self {
+        self.predicate.change.push(Change {
+            pattern,
+            ..Change::default()
+        });
+        self
+    }
+
+    /// Adds an environment, as an alternative to any added before it.
+    /// Environments joined with `&` all have to hold.
+    #[must_use]
+    pub fn environment(mut self, source: &str) -> Self {
+        if let Some(group) = parse_part(environment_group(), source, &mut self.errors) {
+            self.predicate.environment.push(group);
+        }
+        self
+    }
+
+    /// Adds an environment of a single pattern, like
+    /// [`RuleBuilder::environment`] but without parsing it.
+    #[must_use]
+    pub fn environment_pattern(mut self, pattern: Pattern) -> Self {
+        self.predicate.environment.push(EnvironmentGroup {
+            patterns: vec![pattern],
+        });
+        self
+    }
+
+    /// Adds an exception, blocking the rule wherever it holds.
+    #[must_use]
+    pub fn exception(mut self, source: &str) -> Self {
+        if let Some(group) = parse_part(environment_group(), source, &mut self.errors) {
+            self.predicate.exception.push(group);
+        }
+        self
+    }
+
+    /// Adds an exception of a single pattern, like [`RuleBuilder::exception`]
+    /// but without parsing it.
+    #[must_use]
+    pub fn exception_pattern(mut self, pattern: Pattern) -> Self {
+        self.predicate.exception.push(EnvironmentGroup {
+            patterns: vec![pattern],
+        });
+        self
+    }
+
+    #[must_use]
+    pub fn guard(mut self, guard: Guard) -> Self {
+        self.rule.guard = Some(guard);
+        self
+    }
+
+    #[must_use]
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.rule.direction = direction;
+        self
+    }
+
+    /// Makes the rule reapply to its own output until it stops changing the
+    /// word.
+    #[must_use]
+    pub fn iterative(mut self) -> Self {
+        self.rule.iterative = true;
+        self
+    }
+
+    /// Makes the rule sporadic, applying at each match with the given
+    /// percentage of chance.
+    #[must_use]
+    pub fn sporadic(mut self, percent: u8) -> Self {
+        self.rule.sporadic = Some(percent);
+        self
+    }
+
+    /// Caps how many times the rule may replace a match in a single word.
+    #[must_use]
+    pub fn max_applications(mut self, max: usize) -> Self {
+        self.rule.max_applications = Some(max);
+        self
+    }
+
+    /// ## Errors
+    /// Returns the errors of every part which couldn't be parsed, with spans
+    /// into that part's source.
+    pub fn build(mut self) -> Result<Rule, Vec<ParseError>> {
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+        self.rule.predicates.push(self.predicate);
+        Ok(self.rule)
+    }
+}
+
+/// Builds a [`CategoryEdit`], e.g.
+/// `CategoryEditBuilder::new("V").members("a, e").build()` for `V = a, e`.
+#[derive(Debug, Clone)]
+pub struct CategoryEditBuilder {
+    edit: CategoryEdit,
+    errors: Vec<ParseError>,
+}
+
+impl CategoryEditBuilder {
+    /// Starts defining the named category.
+    #[must_use]
+    pub fn new(target: &str) -> Self {
+        let mut errors = vec![];
+        let target = parse_part(text(), target, &mut errors).unwrap_or_default();
+        CategoryEditBuilder {
+            edit: CategoryEdit {
+                target,
+                elements: vec![],
+                kind: CategoryEditKind::Def,
+            },
+            errors,
+        }
+    }
+
+    /// Whether the category is defined, added to or taken from.
+    #[must_use]
+    pub fn kind(mut self, kind: CategoryEditKind) -> Self {
+        self.edit.kind = kind;
+        self
+    }
+
+    /// Adds members, like `a, [C], V-a`, after any added before them.
+    #[must_use]
+    pub fn members(mut self, source: &str) -> Self {
+        if let Some(members) = parse_part(cat_or_els(), source, &mut self.errors) {
+            self.edit.elements.extend(members);
+        }
+        self
+    }
+
+    /// ## Errors
+    /// Returns the errors of every part which couldn't be parsed, with spans
+    /// into that part's source.
+    pub fn build(self) -> Result<CategoryEdit, Vec<ParseError>> {
+        if self.errors.is_empty() {
+            Ok(self.edit)
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod builder_tests {
+    use chumsky::Parser;
+
+    use super::{CategoryEditBuilder, RuleBuilder};
+    use crate::parse::{ast, ASTElement, CategoryEditKind, Direction};
+
+    fn element(source: &str) -> ASTElement {
+        ast().parse(source).into_output().unwrap().elements[0]
+            .0
+            .clone()
+    }
+
+    #[test]
+    fn rule() {
+        let rule = RuleBuilder::new()
+            .target("a")
+            .change("b")
+            .environment("_c")
+            .exception("_#")
+            .build()
+            .unwrap();
+        assert_eq!(ASTElement::Rule(rule), element("a > b / _c ! _#"));
+
+        let rule = RuleBuilder::new()
+            .target("[V]")
+            .target("k")
+            .direction(Direction::RightToLeft)
+            .change("e!_#")
+            .change("h")
+            .environment("#_ & _[C]")
+            .iterative()
+            .build()
+            .unwrap();
+        assert_eq!(
+            ASTElement::Rule(rule),
+            element("[V], k rtl > e!_#, h / #_ & _[C] repeat")
+        );
+    }
+
+    #[test]
+    fn patterns() {
+        use crate::parse::{Pattern, PatternElement::*};
+
+        let pattern = |elements| Pattern { elements };
+        let rule = RuleBuilder::new()
+            .target_pattern(pattern(vec![Text(String::from("a"))]))
+            .position(-1)
+            .change_pattern(pattern(vec![Text(String::from("e"))]))
+            .environment_pattern(pattern(vec![Gap, Text(String::from("b"))]))
+            .environment_pattern(pattern(vec![Text(String::from("c")), Gap]))
+            .exception_pattern(pattern(vec![Gap, Text(String::from("bb"))]))
+            .build()
+            .unwrap();
+        assert_eq!(ASTElement::Rule(rule), element("a@-1 > e / _b, c_ ! _bb"));
+    }
+
+    #[test]
+    fn category_edit() {
+        let edit = CategoryEditBuilder::new("V")
+            .members("a, e")
+            .members("[C], F-o")
+            .build()
+            .unwrap();
+        assert_eq!(ASTElement::CatEdit(edit), element("V = a, e, [C], F-o"));
+
+        let edit = CategoryEditBuilder::new("V")
+            .kind(CategoryEditKind::Sub)
+            .members("a")
+            .build()
+            .unwrap();
+        assert_eq!(ASTElement::CatEdit(edit), element("V -= a"));
+    }
+
+    #[test]
+    fn errors() {
+        let errors = RuleBuilder::new()
+            .target("a")
+            .change("b")
+            .environment("c")
+            .exception("_(d")
+            .build()
+            .unwrap_err();
+        assert_eq!(errors.len(), 2, "{errors:?}");
+
+        let errors = CategoryEditBuilder::new("V=")
+            .members("a,")
+            .build()
+            .unwrap_err();
+        assert_eq!(errors.len(), 2, "{errors:?}");
+    }
+}
@@ -1,6 +1,7 @@
-use strum::EnumString;
+use strum::{Display, EnumString};
 
-#[derive(Clone, Debug, EnumString, PartialEq)]
+#[derive(Clone, Debug, Display, EnumString, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Wildcard {
     #[strum(serialize = "*?")]
     NonGreedy,
@@ -11,3 +12,44 @@ pub enum Wildcard {
     #[strum(serialize = "**")]
     GreedyExtended,
 }
+
+impl Wildcard {
+    /// Whether the wildcard prefers matching as much as possible.
+    #[must_use]
+    pub fn is_greedy(&self) -> bool {
+        matches!(self, Wildcard::Greedy | Wildcard::GreedyExtended)
+    }
+
+    /// Whether the wildcard may match across word boundaries.
+    #[must_use]
+    pub fn is_extended(&self) -> bool {
+        matches!(self, Wildcard::GreedyExtended | Wildcard::NonGreedyExtended)
+    }
+}
+
+/// A small seedable random number generator (`SplitMix64`), so that sporadic
+/// rules apply the same way every time for the same seed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Whether something with the given percentage of chance happens.
+    pub fn chance(&mut self, percent: u8) -> bool {
+        self.next_u64() % 100 < u64::from(percent)
+    }
+}
@@ -0,0 +1,344 @@
+//! Applies rules one at a time, so that the words can be inspected between
+//! rules and steps can be taken back, as in an interactive editor.
+
+use std::collections::HashMap;
+
+use crate::{
+    apply::{
+        apply_rule, declared_phonology, edit_category, ApplyError, Category, InterpreterState,
+        PendingCategory, Predicates, Warning,
+    },
+    common::Rng,
+    parse::{ASTElement, AST},
+    word::Word,
+};
+
+/// The words and categories as they were right before an element was gone
+/// through.
+struct Step {
+    /// The index of the element among all the elements.
+    element: usize,
+    /// The index of the next rule among the rules.
+    rule: usize,
+    /// The words, if the element was a rule. Nothing else changes them, so
+    /// they're only kept for rules.
+    words: Option<Vec<Word>>,
+    categories: HashMap<String, Category>,
+    pending: Vec<(String, PendingCategory)>,
+    warnings: Vec<Warning>,
+    rng: Rng,
+}
+
+/// Steps through the rules of an AST, keeping a history of the words and
+/// categories before each element so that rules can be undone, and edited
+/// rules reapplied from where they changed.
+pub struct Engine {
+    elements: Vec<ASTElement>,
+    /// The index of the next element to go through.
+    next: usize,
+    /// The index of the next rule among the rules.
+    rule: usize,
+    state: InterpreterState,
+    words: Vec<Word>,
+    history: Vec<Step>,
+    predicates: Predicates,
+    /// Decides where sporadic rules apply, seeded with 0.
+    rng: Rng,
+}
+
+impl Engine {
+    /// Prepares to apply the rules of `ast` to `words`, parsing the words with
+    /// the given graphs and separators unless the rules declare their own.
+    #[must_use]
+    pub fn new(ast: AST, words: &[String], graphs: Vec<String>, separators: Vec<String>) -> Self {
+        let (graphs, separators) = declared_phonology(&ast, graphs, separators);
+        let words = words
+            .iter()
+            .map(|word| crate::word::parse(word, graphs.clone(), separators.clone()))
+            .collect();
+
+        Engine {
+            elements: ast
+                .elements
+                .into_iter()
+                .map(|(element, _)| element)
+                .collect(),
+            next: 0,
+            rule: 0,
+            state: InterpreterState::new(graphs, separators),
+            words,
+            history: vec![],
+            predicates: Predicates::new(),
+            rng: Rng::new(0),
+        }
+    }
+
+    /// Registers a condition on single phones, which patterns can then refer
+    /// to as `{:name}`, replacing any registered under the same name.
+    pub fn register_predicate(
+        &mut self,
+        name: impl Into<String>,
+        predicate: impl Fn(&str) -> bool + 'static,
+    ) {
+        self.predicates.insert(name.into(), Box::new(predicate));
+    }
+
+    /// Applies the next rule to every word, going through any category edits
+    /// before it.
+    ///
+    /// ## Returns
+    /// Whether there was a rule left to apply.
+    ///
+    /// ## Errors
+    /// Errors if the rule can't be applied, in which case the words are left
+    /// as they were.
+    pub fn step(&mut self) -> Result<bool, ApplyError> {
+        while self.next < self.elements.len() {
+            let mut step = self.snapshot();
+            match &self.elements[self.next] {
+                ASTElement::Rule(rule) => {
+                    let rng = self.rng;
+                    let applied = self
+                        .words
+                        .iter()
+                        .map(|word| {
+                            #[cfg(feature = "tracing")]
+                            let _span =
+                                tracing::trace_span!("rule", index = self.rule, word = %word)
+                                    .entered();
+                            let (categories, predicates) =
+                                (&self.state.categories, &self.predicates);
+                            apply_rule(rule, word, categories, predicates, &mut self.rng)
+                                .map_err(|e| e.in_rule(self.rule, word))
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                        .inspect_err(|_| self.rng = rng)?;
+                    step.words = Some(std::mem::replace(&mut self.words, applied));
+                    self.history.push(step);
+                    self.next += 1;
+                    self.rule += 1;
+                    return Ok(true);
+                }
+                ASTElement::CatEdit(edit) => edit_category(&mut self.state, edit.clone()),
+                // already taken into account when parsing the words
+                ASTElement::Graphs(_)
+                | ASTElement::Separator(_)
+                | ASTElement::Checkpoint(_)
+                | ASTElement::Stage(_)
+                | ASTElement::Trace(_) => {}
+            }
+            self.history.push(step);
+            self.next += 1;
+        }
+        Ok(false)
+    }
+
+    /// The state right before the next element, without the words.
+    fn snapshot(&self) -> Step {
+        Step {
+            element: self.next,
+            rule: self.rule,
+            words: None,
+            categories: self.state.categories.clone(),
+            pending: self.state.pending.clone(),
+            warnings: self.state.warnings.clone(),
+            rng: self.rng,
+        }
+    }
+
+    /// Goes back to the state right before the element of the step.
+    fn restore(&mut self, step: Step) {
+        self.next = step.element;
+        self.rule = step.rule;
+        if let Some(words) = step.words {
+            self.words = words;
+        }
+        self.state.categories = step.categories;
+        self.state.pending = step.pending;
+        self.state.warnings = step.warnings;
+        self.rng = step.rng;
+    }
+
+    /// Applies all the rules left.
+    ///
+    /// ## Errors
+    /// Errors if a rule can't be applied; see [`Engine::step`].
+    pub fn run(&mut self) -> Result<(), ApplyError> {
+        while self.step()? {}
+        Ok(())
+    }
+
+    /// Reverts the words to how they were before the last rule applied, so
+    /// that the next step applies it again.
+    ///
+    /// ## Returns
+    /// Whether there was a rule to undo.
+    pub fn undo(&mut self) -> bool {
+        if !self.history.iter().any(|step| step.words.is_some()) {
+            return false;
+        }
+        // along with any category edits gone through since
+        while let Some(step) = self.history.pop() {
+            let rule = step.words.is_some();
+            self.restore(step);
+            if rule {
+                break;
+            }
+        }
+        true
+    }
+
+    /// Replaces the rules with those of `ast`, as after editing them, going
+    /// back to the words and categories as they were right before the first
+    /// element that changed. Running again then only goes through the elements
+    /// from there on, so the rules before it aren't applied again.
+    ///
+    /// Any graphs or separators declared are still those of the rules the
+    /// engine was made with, since the words have already been parsed.
+    pub fn update(&mut self, ast: AST) {
+        let elements: Vec<_> = ast
+            .elements
+            .into_iter()
+            .map(|(element, _)| element)
+            .collect();
+        let unchanged = self
+            .elements
+            .iter()
+            .zip(&elements)
+            .take_while(|(old, new)| old == new)
+            .count();
+
+        while let Some(step) = self.history.pop_if(|step| step.element >= unchanged) {
+            self.restore(step);
+        }
+        self.elements = elements;
+    }
+
+    /// The words as they are now.
+    #[must_use]
+    pub fn words(&self) -> Vec<String> {
+        self.words.iter().map(ToString::to_string).collect()
+    }
+}
+
+#[cfg(test)]
+mod engine_tests {
+    use super::Engine;
+    use crate::{apply::ApplyError, parse::ast};
+    use chumsky::Parser;
+    use std::{cell::Cell, rc::Rc};
+
+    #[test]
+    fn undo() {
+        let parsed = ast()
+            .parse("a > e\nV = e\nV += i\n[V] > o / _t")
+            .into_output()
+            .unwrap();
+        let words = ["kat", "tak"].map(String::from);
+        let mut engine = Engine::new(parsed, &words, vec![], vec![String::from("'")]);
+
+        engine.run().unwrap();
+        assert_eq!(engine.words(), vec!["kot", "tek"]);
+
+        assert!(engine.undo());
+        assert_eq!(engine.words(), vec!["ket", "tek"]);
+
+        assert!(engine.step().unwrap());
+        assert_eq!(engine.words(), vec!["kot", "tek"]);
+        assert!(!engine.step().unwrap());
+
+        assert!(engine.undo());
+        assert!(engine.undo());
+        assert!(!engine.undo());
+        assert_eq!(engine.words(), vec!["kat", "tak"]);
+    }
+
+    #[test]
+    fn predicate() {
+        let parsed = ast()
+            .parse("{:vowel} > x / _t\n{:unknown} > y")
+            .into_output()
+            .unwrap();
+        let words = ["kat", "atit", "ktt"].map(String::from);
+        let mut engine = Engine::new(parsed, &words, vec![], vec![String::from("'")]);
+        engine.register_predicate("vowel", |phone| "aeiou".contains(phone));
+
+        assert!(engine.step().unwrap());
+        assert_eq!(engine.words(), vec!["kxt", "xtxt", "ktt"]);
+
+        // a predicate which isn't registered is most likely a typo
+        let error = engine.step().unwrap_err();
+        assert_eq!(
+            error,
+            ApplyError::UndefinedPredicate {
+                rule: 1,
+                word: String::from("kxt"),
+                predicate: String::from("unknown"),
+            }
+        );
+    }
+
+    #[test]
+    fn update() {
+        let source = "{:counted} > x / _t\nV = a, x\n[V] > e / k_";
+        let words = ["kat", "tak", "atit"].map(String::from);
+        let mut engine = Engine::new(
+            ast().parse(source).into_output().unwrap(),
+            &words,
+            vec![],
+            vec![String::from("'")],
+        );
+        let calls = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&calls);
+        engine.register_predicate("counted", move |phone| {
+            counter.set(counter.get() + 1);
+            phone == "a" || phone == "i"
+        });
+
+        engine.run().unwrap();
+        assert_eq!(engine.words(), vec!["ket", "tak", "xtxt"]);
+        let first_run = calls.get();
+
+        // editing the last rule leaves the first alone
+        let edited = "{:counted} > x / _t\nV = a, x\n[V] > o / k_";
+        engine.update(ast().parse(edited).into_output().unwrap());
+        engine.run().unwrap();
+        assert_eq!(engine.words(), vec!["kot", "tak", "xtxt"]);
+        assert_eq!(calls.get(), first_run);
+
+        // as does editing a category after it
+        let edited = "{:counted} > x / _t\nV = a\n[V] > o / k_";
+        engine.update(ast().parse(edited).into_output().unwrap());
+        engine.run().unwrap();
+        assert_eq!(engine.words(), vec!["kxt", "tak", "xtxt"]);
+        assert_eq!(calls.get(), first_run);
+
+        // while editing the first rule reapplies everything
+        let edited = "{:counted} > y / _t\nV = a\n[V] > o / k_";
+        engine.update(ast().parse(edited).into_output().unwrap());
+        engine.run().unwrap();
+        assert_eq!(engine.words(), vec!["kyt", "tak", "ytyt"]);
+        assert_eq!(calls.get(), 2 * first_run);
+    }
+
+    #[test]
+    fn update_warnings() {
+        let parse = |source| ast().parse(source).into_output().unwrap();
+        let words = [String::from("ab")];
+        let mut engine = Engine::new(parse("B = [A]\nb > c"), &words, vec![], vec![]);
+        engine.run().unwrap();
+        assert_eq!(engine.state.warnings.len(), 1);
+
+        // the warning about `A` goes along with the edit which gave it
+        engine.update(parse("B = [C]\nb > c"));
+        engine.run().unwrap();
+        assert_eq!(
+            engine.state.warnings,
+            vec![super::Warning::UndefinedCategory {
+                category: String::from("B"),
+                reference: String::from("C"),
+            }]
+        );
+        assert_eq!(engine.words(), vec!["ac"]);
+    }
+}
@@ -0,0 +1,309 @@
+//! Writes syntax trees back out as SCE source, for tools like formatters which
+//! edit rules rather than apply them.
+//!
+//! The source written is canonical rather than what was parsed: comments,
+//! rule groups and tables are gone, and every rule is written out in full, so
+//! that `+ a / _b` comes back as `[] > a / _b`. Parsing it again gives the same
+//! elements, though with different spans.
+
+use std::fmt::{Display, Formatter, Result};
+
+use crate::parse::{
+    ASTElement, CatOrEl, CategoryEdit, CategoryEditKind, Change, Direction, EnvironmentGroup,
+    Guard, Pattern, PatternElement, Predicate, Rule, Target, AST, CONTROL_CHARACTERS,
+};
+
+/// Writes an AST back out as source, one element per line.
+#[must_use]
+pub fn format(ast: &AST) -> String {
+    ast.to_string()
+}
+
+/// The text with each control character escaped, so that it's read back as
+/// text.
+fn escaped(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if CONTROL_CHARACTERS.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Writes `items` with `separator` between each of them.
+fn write_separated<T: Display>(f: &mut Formatter<'_>, items: &[T], separator: &str) -> Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            f.write_str(separator)?;
+        }
+        write!(f, "{item}")?;
+    }
+    Ok(())
+}
+
+/// Writes the members of a category. A category operation takes up the rest
+/// of the list, so one which isn't last is bracketed.
+fn write_members(f: &mut Formatter<'_>, members: &[CatOrEl]) -> Result {
+    for (i, member) in members.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        let operation = matches!(member, CatOrEl::Without(..) | CatOrEl::Intersection(..));
+        if operation && i + 1 < members.len() {
+            write!(f, "[{member}]")?;
+        } else {
+            write!(f, "{member}")?;
+        }
+    }
+    Ok(())
+}
+
+impl Display for CatOrEl {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            CatOrEl::Cat(name) => write!(f, "[{}]", escaped(name)),
+            CatOrEl::El(member) => f.write_str(&escaped(member)),
+            CatOrEl::Null => f.write_str("[]"),
+            CatOrEl::Without(name, operand) => {
+                write!(f, "{}-", escaped(name))?;
+                write_members(f, operand)
+            }
+            CatOrEl::Intersection(name, operand) => {
+                write!(f, "{}&", escaped(name))?;
+                write_members(f, operand)
+            }
+        }
+    }
+}
+
+impl Display for CategoryEdit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let kind = match self.kind {
+            CategoryEditKind::Def => "=",
+            CategoryEditKind::Add => "+=",
+            CategoryEditKind::Sub => "-=",
+        };
+        write!(f, "{} {kind} ", escaped(&self.target))?;
+        write_members(f, &self.elements)
+    }
+}
+
+impl Display for PatternElement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        use PatternElement::*;
+
+        match self {
+            Text(text) => f.write_str(&escaped(text)),
+            Optional(pattern) => write!(f, "({pattern})"),
+            OptionalNonGreedy(pattern) => write!(f, "({pattern})?"),
+            Wildcard(wildcard) => write!(f, "{wildcard}"),
+            RepeatN(times) => write!(f, "{{{times}}}"),
+            RepeatRange { min, max: None } => write!(f, "{{{min},}}"),
+            RepeatRange {
+                min,
+                max: Some(max),
+            } => write!(f, "{{{min},{max}}}"),
+            RepeatWild(wildcard) => write!(f, "{{{wildcard}}}"),
+            RepeatCount(0) => f.write_str("{#}"),
+            RepeatCount(offset) => write!(f, "{{#{offset:+}}}"),
+            CatRef(name) => write!(f, "[{}]", escaped(name)),
+            Category(members) => {
+                f.write_str("[")?;
+                write_members(f, members)?;
+                f.write_str("]")
+            }
+            NegatedCategory(members) => {
+                f.write_str("[!")?;
+                write_members(f, members)?;
+                f.write_str("]")
+            }
+            PredicateRef(name) => write!(f, "{{:{}}}", escaped(name)),
+            Ditto => f.write_str("\""),
+            Target => f.write_str("%"),
+            TargetReversed => f.write_str("<"),
+            Boundary => f.write_str("#"),
+            OptionalBoundary => f.write_str("#?"),
+            Gap => f.write_str("_"),
+            SyllableBoundary => f.write_str("."),
+            NegativeLookahead(pattern) => write!(f, "(?!{pattern})"),
+            NegativeLookbehind(pattern) => write!(f, "(?<!{pattern})"),
+        }
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write_separated(f, &self.elements, "")
+    }
+}
+
+impl Display for EnvironmentGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write_separated(f, &self.patterns, " & ")
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", self.pattern)?;
+        for exception in &self.exception {
+            write!(f, "!{exception}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Predicate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.write_str("> ")?;
+        write_separated(f, &self.change, ", ")?;
+        if !self.environment.is_empty() {
+            f.write_str(" / ")?;
+            write_separated(f, &self.environment, ", ")?;
+        }
+        if !self.exception.is_empty() {
+            f.write_str(" ! ")?;
+            write_separated(f, &self.exception, ", ")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write_separated(f, &self.patterns, ", ")?;
+        if !self.positions.is_empty() {
+            f.write_str("@")?;
+            write_separated(f, &self.positions, "|")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Guard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "if len {} {}", self.comparison, self.length)
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", self.target)?;
+        if self.direction == Direction::RightToLeft {
+            f.write_str(" rtl")?;
+        }
+        for predicate in &self.predicates {
+            write!(f, " {predicate}")?;
+        }
+        if let Some(guard) = &self.guard {
+            write!(f, " {guard}")?;
+        }
+        if let Some(percent) = self.sporadic {
+            write!(f, " ?{percent}%")?;
+        }
+        if self.iterative {
+            f.write_str(" repeat")?;
+        }
+        if let Some(max) = self.max_applications {
+            write!(f, " max {max}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for ASTElement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            ASTElement::Rule(rule) => write!(f, "{rule}"),
+            ASTElement::CatEdit(edit) => write!(f, "{edit}"),
+            ASTElement::Graphs(graphs) => {
+                f.write_str("graphs: ")?;
+                write_separated(f, graphs, ", ")
+            }
+            ASTElement::Separator(separators) => {
+                f.write_str("separator: ")?;
+                write_separated(f, separators, ", ")
+            }
+            ASTElement::Checkpoint(name) => write!(f, "checkpoint \"{name}\""),
+            ASTElement::Stage(name) => write!(f, "stage: {name}"),
+            ASTElement::Trace(words) => {
+                f.write_str("trace: ")?;
+                write_separated(f, words, ", ")
+            }
+        }
+    }
+}
+
+impl Display for AST {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        for (element, _) in &self.elements {
+            writeln!(f, "{element}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod format_tests {
+    use chumsky::Parser;
+
+    use crate::parse::{ast, bench::SAXONISH, ASTElement};
+
+    fn elements(source: &str) -> Vec<ASTElement> {
+        ast()
+            .parse(source)
+            .into_output()
+            .unwrap()
+            .elements
+            .into_iter()
+            .map(|(element, _)| element)
+            .collect()
+    }
+
+    fn round_trip(source: &str) {
+        let formatted = super::format(&ast().parse(source).into_result().unwrap());
+        assert_eq!(elements(&formatted), elements(source), "{formatted}");
+    }
+
+    #[test]
+    fn saxonish() {
+        round_trip(SAXONISH);
+    }
+
+    #[test]
+    fn canonical() {
+        let cases = [
+            ("V=a,e", "V = a, e\n"),
+            ("+ a / _b", "[] > a / _b\n"),
+            ("- a@-1 rtl", "a@-1 rtl > []\n"),
+            ("a>b/_c!d_", "a > b / _c ! d_\n"),
+            ("\\[ > \\,", "\\[ > \\,\n"),
+            ("a > b ?", "a > b ?50%\n"),
+        ];
+        for (source, expected) in cases {
+            let formatted = super::format(&ast().parse(source).into_output().unwrap());
+            assert_eq!(formatted, expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let sources = [
+            "C = p, [V], V-a, [V&F, e], [V-a], e",
+            "C += [], a\nC -= a",
+            "[V-a,e]{2} > %< / #?_(?!b) & (?<!c)_ ! [!a,b]_",
+            "a{*?}b{#}c{**} > \"{#-1}e!_#, o / _(x)?.",
+            "a{2,3}b{1,} > c",
+            "a, e@1|-2 rtl > e, i / _{:vowel} if len <= 3 ?30% repeat max 2",
+            "{ a > b; c > d } / _# ! e_",
+            "table: a:á \\[:b",
+            "a#\\?b > c\\ d",
+            "graphs: sh, ts\nseparator: ', .\ncheckpoint \"West Germanic\"\nstage: ingvaeonic\ntrace: hund",
+        ];
+        for source in sources {
+            round_trip(source);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Checks rules against a file of expected outcomes, so that sound changes can
+//! be regression-tested.
+//!
+//! Each non-empty line of a test file is a case of the form
+//! `input => expected`.
+
+use std::fmt::Display;
+
+use crate::{
+    apply::{apply, ApplyError},
+    parse::AST,
+};
+
+/// A word and what it's expected to become after applying the rules.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestCase {
+    /// The line of the test file the case is on, starting from 1.
+    pub line: usize,
+    pub input: String,
+    pub expected: String,
+}
+
+/// The outcome of a test case.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaseResult {
+    pub case: TestCase,
+    /// What the input actually became.
+    pub actual: String,
+}
+
+impl CaseResult {
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.case.expected == self.actual
+    }
+}
+
+/// The outcomes of all the test cases of a file, in order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestReport {
+    pub results: Vec<CaseResult>,
+}
+
+impl TestReport {
+    /// The cases that didn't pass.
+    pub fn failures(&self) -> impl Iterator<Item = &CaseResult> {
+        self.results.iter().filter(|result| !result.passed())
+    }
+
+    #[must_use]
+    pub fn all_passed(&self) -> bool {
+        self.failures().next().is_none()
+    }
+}
+
+impl Display for TestReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for result in self.failures() {
+            let TestCase {
+                line,
+                input,
+                expected,
+            } = &result.case;
+            writeln!(
+                f,
+                "line {line}: {input} => {expected}, but got {}",
+                result.actual
+            )?;
+        }
+        let failed = self.failures().count();
+        write!(f, "{} passed, {failed} failed", self.results.len() - failed)
+    }
+}
+
+/// Parses test cases, one `input => expected` per line. Empty lines are
+/// skipped.
+///
+/// ## Errors
+/// Returns the number of the first line which isn't a test case.
+pub fn parse_cases(source: &str) -> Result<Vec<TestCase>, usize> {
+    source
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let (input, expected) = line.split_once("=>").ok_or(i + 1)?;
+            Ok(TestCase {
+                line: i + 1,
+                input: input.trim().to_string(),
+                expected: expected.trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Applies the rules to the input of each test case, comparing the results to
+/// what was expected.
+///
+/// ## Errors
+/// Errors if the rules can't be applied.
+pub fn run(
+    ast: AST,
+    cases: Vec<TestCase>,
+    graphs: Vec<String>,
+    separators: Vec<String>,
+) -> Result<TestReport, ApplyError> {
+    let words = cases.iter().map(|case| case.input.clone()).collect();
+    let (words, _) = apply(ast, words, graphs, separators)?;
+
+    let results = cases
+        .into_iter()
+        .zip(words)
+        .map(|(case, actual)| CaseResult { case, actual })
+        .collect();
+    Ok(TestReport { results })
+}
+
+#[cfg(test)]
+mod harness_tests {
+    use super::{parse_cases, run};
+    use crate::parse::ast;
+    use chumsky::Parser;
+
+    #[test]
+    fn report() {
+        let ast = ast().parse("a > e / _#").into_output().unwrap();
+        let cases = parse_cases("bata => bate\n\nabab => ebab\n").unwrap();
+        let report = run(ast, cases, vec![], vec![String::from("'")]).unwrap();
+
+        assert!(report.results[0].passed());
+        assert!(!report.results[1].passed());
+        assert!(!report.all_passed());
+        assert_eq!(
+            report.to_string(),
+            "line 3: abab => ebab, but got abab\n1 passed, 1 failed"
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(parse_cases("a => b\nab\n"), Err(2));
+    }
+}
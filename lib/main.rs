@@ -1,50 +1,52 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::enum_glob_use)]
-#![feature(test)]
+#![cfg_attr(test, feature(test))]
 
-use ariadne::{sources, Label, Report};
 use chumsky::prelude::*;
 use parse::AST;
+use report::ParseError;
 
 pub mod apply;
+pub mod builder;
 pub mod common;
+pub mod engine;
+pub mod format;
+pub mod harness;
 pub mod parse;
+pub mod prelude;
+pub mod report;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 pub mod word;
 
+pub use apply::apply;
+
 /// Parses source code into an SCE AST.
 ///
 /// ## Panics
 /// Panics if it fails to make error reports.
 ///
 /// ## Returns
-/// Either the AST or the errors encountered.
+/// Either the AST or the errors encountered, which are also reported to
+/// stderr; see [`report::write_reports`].
 ///
 /// ## Errors
-/// Returns parse errors.
-pub fn parse(source: &str) -> Result<AST, Vec<Rich<char>>> {
+/// Returns parse errors, with the lines and columns they were found at.
+pub fn parse(source: &str) -> Result<AST, Vec<ParseError>> {
     let (ast, errs) = parse::ast().parse(source).into_output_errors();
-    if let Some(ast) = ast {
-        println!("ast: {ast:?}");
+    if let (Some(ast), true) = (ast, errs.is_empty()) {
+        log::trace!("ast: {ast:?}");
         return Ok(ast);
     }
-    errs.clone()
-        .into_iter()
-        .map(|e| e.map_token(|c| c.to_string()))
-        .for_each(|e| {
-            Report::build(
-                ariadne::ReportKind::Error,
-                String::from("src"),
-                e.span().start,
-            )
-            .with_message(format!("{e:?}"))
-            .with_label(
-                Label::new((String::from("src"), e.span().into_range()))
-                    .with_message(format!("{e:?}")),
-            )
-            .finish()
-            .print(sources([(String::from("src"), source)]))
-            .unwrap();
-        });
+    // an unbalanced bracket throws the parser off wherever it gives up, so it's
+    // reported on its own
+    let unbalanced = report::unbalanced_delimiters(source);
+    let errs: Vec<_> = if unbalanced.is_empty() {
+        errs.iter().map(|e| ParseError::new(source, e)).collect()
+    } else {
+        unbalanced
+    };
+    report::write_reports(source, &errs, std::io::stderr()).unwrap();
 
     Err(errs)
 }
@@ -1,29 +1,43 @@
 use chumsky::{
     prelude::*,
-    text::{digits, inline_whitespace, newline, whitespace},
+    text::{digits, newline, whitespace},
 };
-use std::str::FromStr;
+use std::{ops::Range, str::FromStr};
+use strum::{Display, EnumString};
 
-use crate::common::Wildcard;
+use crate::{builder::RuleBuilder, common::Wildcard};
 
-const CONTROL_CHARACTERS: &str = "[]{}<>()@!%^_, *?\\+-^/=";
+pub(crate) const CONTROL_CHARACTERS: &str = "[]{}<>()@!%^_, *?\\+-^/=.&;\"";
 
-type E<'a> = extra::Err<Rich<'a, char, SimpleSpan<usize>>>;
+pub(crate) type E<'a> = extra::Err<Rich<'a, char, SimpleSpan<usize>>>;
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CatOrEl {
     Cat(String),
     El(String),
+    /// An empty member (`[]`), which matches nothing at all.
+    Null,
+    /// The members of the named category, other than those listed after it
+    /// (`V-a,e`), which takes up the rest of the list unless bracketed.
+    /// Listed members naming a category stand for its members.
+    Without(String, Vec<CatOrEl>),
+    /// The members of the named category which are also listed after it
+    /// (`V&F`, or `V&a,e`), in the category's order. Like [`CatOrEl::Without`],
+    /// it takes up the rest of the list unless bracketed.
+    Intersection(String, Vec<CatOrEl>),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CategoryEditKind {
     Def,
     Add,
     Sub,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CategoryEdit {
     pub target: String,
     pub elements: Vec<CatOrEl>,
@@ -46,10 +60,32 @@ mod escape_tests {
     }
 }
 
-fn text<'a>() -> impl Parser<'a, &'a str, String, E<'a>> {
+/// Parses a space or tab, or a line break escaped by a `\` before it, which
+/// continues an element onto the next line, as in `a > b /\` followed by
+/// `_c` on the next line.
+fn inline_space<'a>() -> impl Parser<'a, &'a str, (), E<'a>> + Clone {
+    let continuation = just('\\')
+        .then(one_of(" \t").repeated())
+        .then(newline().or(end().validate(|(), span, emitter| {
+            emitter.emit(Rich::custom(
+                span,
+                "a line continuation `\\` must be followed by a line break",
+            ));
+        })))
+        .ignored();
+    one_of(" \t").ignored().or(continuation)
+}
+
+/// Parses any amount of inline whitespace; see [`inline_space`].
+fn inline_whitespace<'a>() -> impl Parser<'a, &'a str, (), E<'a>> + Clone {
+    inline_space().repeated()
+}
+
+pub(crate) fn text<'a>() -> impl Parser<'a, &'a str, String, E<'a>> {
     none_of(CONTROL_CHARACTERS)
         .and_is(whitespace().at_least(1).not())
         .and_is(escape().not())
+        .and_is(just("#?").not())
         .or(escape())
         .repeated()
         .at_least(1)
@@ -58,6 +94,19 @@ fn text<'a>() -> impl Parser<'a, &'a str, String, E<'a>> {
         .collect::<String>()
 }
 
+/// Parses text in a pattern, which unlike other text stops at a `#`, since
+/// that's a word boundary there.
+fn pattern_text<'a>() -> impl Parser<'a, &'a str, String, E<'a>> {
+    none_of(CONTROL_CHARACTERS)
+        .and_is(whitespace().at_least(1).not())
+        .and_is(escape().not())
+        .and_is(just('#').not())
+        .or(escape())
+        .repeated()
+        .at_least(1)
+        .collect::<String>()
+}
+
 #[cfg(test)]
 mod text_tests {
     use chumsky::Parser;
@@ -81,14 +130,30 @@ mod text_tests {
     }
 }
 
-fn cat_or_els<'a>() -> impl Parser<'a, &'a str, Vec<CatOrEl>, E<'a>> {
-    text()
-        .delimited_by(just('['), just(']'))
-        .map(CatOrEl::Cat)
-        .or(text().map(CatOrEl::El))
+pub(crate) fn cat_or_els<'a>() -> impl Parser<'a, &'a str, Vec<CatOrEl>, E<'a>> + Clone {
+    recursive(|cat_or_els| {
+        let operation = || {
+            text()
+                .then(one_of("-&"))
+                .then(cat_or_els.clone())
+                .map(|((name, operator), operand)| match operator {
+                    '-' => CatOrEl::Without(name, operand),
+                    _ => CatOrEl::Intersection(name, operand),
+                })
+        };
+
+        choice((
+            just("[]").to(CatOrEl::Null),
+            operation().delimited_by(just('['), just(']')),
+            text().delimited_by(just('['), just(']')).map(CatOrEl::Cat),
+            operation(),
+            text().map(CatOrEl::El),
+        ))
         .separated_by(just(',').then_ignore(inline_whitespace()))
         .at_least(1)
         .collect::<Vec<_>>()
+        .boxed()
+    })
 }
 
 #[cfg(test)]
@@ -105,9 +170,43 @@ mod cat_or_els_tests {
                 Cat(String::from("c"))
             ])
         );
+        assert_eq!(
+            super::cat_or_els().parse("a,[]").into_output(),
+            Some(vec![El(String::from("a")), Null])
+        );
+        assert_eq!(
+            super::cat_or_els().parse("V-a,e").into_output(),
+            Some(vec![Without(
+                String::from("V"),
+                vec![El(String::from("a")), El(String::from("e"))]
+            )])
+        );
+        assert_eq!(
+            super::cat_or_els().parse("[V-a],o").into_output(),
+            Some(vec![
+                Without(String::from("V"), vec![El(String::from("a"))]),
+                El(String::from("o"))
+            ])
+        );
+        assert_eq!(
+            super::cat_or_els().parse("V&F").into_output(),
+            Some(vec![Intersection(
+                String::from("V"),
+                vec![El(String::from("F"))]
+            )])
+        );
+        assert_eq!(
+            super::cat_or_els().parse("\\], \\,,[\\[]").into_output(),
+            Some(vec![
+                El(String::from("]")),
+                El(String::from(",")),
+                Cat(String::from("["))
+            ])
+        );
     }
 }
 
+#[must_use]
 pub fn cat_edit<'a>() -> impl Parser<'a, &'a str, CategoryEdit, E<'a>> {
     let kind = choice((
         just('=').to(CategoryEditKind::Def),
@@ -128,18 +227,85 @@ pub fn cat_edit<'a>() -> impl Parser<'a, &'a str, CategoryEdit, E<'a>> {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PatternElement {
     Text(String),
     Optional(Pattern),
     OptionalNonGreedy(Pattern),
     Wildcard(Wildcard),
     RepeatN(usize),
+    /// Repeats the element before it between `min` and `max` times, or at
+    /// least `min` times with no `max` (`{2,4}`, `{2,}`), as many as possible.
+    RepeatRange {
+        min: usize,
+        max: Option<usize>,
+    },
     RepeatWild(Wildcard),
+    /// In a change, repeats the element before it as many times as the
+    /// repetition in the target matched, plus an offset (`{#}`, `{#-1}`).
+    RepeatCount(isize),
     CatRef(String),
     Category(Vec<CatOrEl>),
+    /// Any single phone which isn't a word boundary or one of the members
+    /// (`[!a,b,c]`).
+    NegatedCategory(Vec<CatOrEl>),
+    /// Any single phone, other than a word boundary, for which the predicate
+    /// registered under the name holds (`{:name}`).
+    PredicateRef(String),
     Ditto,
     Target,
     TargetReversed,
+    /// A word boundary (`#`), which matches only the boundaries around and
+    /// between the words of the input, never text.
+    Boundary,
+    /// A word boundary, or nothing (`#?`).
+    OptionalBoundary,
+    /// Where the target sits within an environment (`_`).
+    Gap,
+    /// A syllable break (`.`), as given by the word's syllabification; see
+    /// [`crate::word::parse_syllabified`]. A `.` phone is written `\.`.
+    SyllableBoundary,
+    /// Asserts that the pattern does not match right after this point, without
+    /// consuming anything (`(?!...)`).
+    NegativeLookahead(Pattern),
+    /// Asserts that the pattern does not match right before this point, without
+    /// consuming anything (`(?<!...)`).
+    NegativeLookbehind(Pattern),
+}
+
+impl PatternElement {
+    /// Whether this element repeats the one before it.
+    #[must_use]
+    pub fn is_repetition(&self) -> bool {
+        matches!(
+            self,
+            PatternElement::RepeatN(_)
+                | PatternElement::RepeatRange { .. }
+                | PatternElement::RepeatWild(_)
+                | PatternElement::RepeatCount(_)
+        )
+    }
+
+    /// Whether this element means anything in a change, which only says what
+    /// replaces the target, so can't match several ways, as a wildcard or an
+    /// optional part would, or merely check the surroundings, as a gap does.
+    #[must_use]
+    pub fn can_replace(&self) -> bool {
+        use PatternElement::*;
+
+        matches!(
+            self,
+            Text(_)
+                | CatRef(_)
+                | Category(_)
+                | Ditto
+                | Target
+                | TargetReversed
+                | Boundary
+                | RepeatN(_)
+                | RepeatCount(_)
+        )
+    }
 }
 
 pub fn pattern_element<'src>(
@@ -153,6 +319,16 @@ pub fn pattern_element<'src>(
 
     let wildcard = wildcard_inner.map(PatternElement::Wildcard);
 
+    let negative_lookahead = pattern
+        .clone()
+        .delimited_by(just("(?!"), just(')'))
+        .map(PatternElement::NegativeLookahead);
+
+    let negative_lookbehind = pattern
+        .clone()
+        .delimited_by(just("(?<!"), just(')'))
+        .map(PatternElement::NegativeLookbehind);
+
     let optional_non_greedy = pattern
         .clone()
         .delimited_by(just('('), just(")?"))
@@ -162,14 +338,44 @@ pub fn pattern_element<'src>(
         .delimited_by(just('('), just(')'))
         .map(PatternElement::Optional);
 
-    let repeat_n = digits(10)
-        .slice()
-        .try_map(|t: &str, span| {
+    let number = || {
+        digits(10).slice().try_map(|t: &str, span| {
             t.parse::<usize>()
                 .map_err(|_| Rich::custom(span, "couldn't parse repeat int"))
         })
+    };
+
+    let repeat_n = number()
         .delimited_by(just('{'), just('}'))
         .map(PatternElement::RepeatN);
+    let repeat_range = number()
+        .then_ignore(just(','))
+        .then(number().or_not())
+        .delimited_by(just('{'), just('}'))
+        .validate(|(min, max), span, emitter| {
+            if max.is_some_and(|max| max < min) {
+                emitter.emit(Rich::custom(
+                    span,
+                    "a repeat range's maximum can't be less than its minimum",
+                ));
+            }
+            PatternElement::RepeatRange { min, max }
+        });
+
+    let repeat_count = just('#')
+        .ignore_then(
+            one_of("+-")
+                .then(digits(10))
+                .slice()
+                .try_map(|t: &str, span| {
+                    t.parse::<isize>()
+                        .map_err(|_| Rich::custom(span, "couldn't parse repeat count offset"))
+                })
+                .or_not()
+                .map(Option::unwrap_or_default),
+        )
+        .delimited_by(just('{'), just('}'))
+        .map(PatternElement::RepeatCount);
 
     let repeat_wild = wildcard_inner
         .delimited_by(just('{'), just('}'))
@@ -179,42 +385,100 @@ pub fn pattern_element<'src>(
         .delimited_by(just('['), just(']'))
         .map(PatternElement::CatRef);
 
+    let predicate_ref = text()
+        .delimited_by(just("{:"), just('}'))
+        .map(PatternElement::PredicateRef);
+
     let null_category = just("[]").to(PatternElement::Category(vec![]));
 
     let category = cat_or_els()
         .delimited_by(just('['), just(']'))
         .map(PatternElement::Category);
 
+    let negated_category = cat_or_els()
+        .delimited_by(just("[!"), just(']'))
+        .map(PatternElement::NegatedCategory);
+
     let simple = choice((
         just('%').to(PatternElement::Target),
         just('"').to(PatternElement::Ditto),
         just('<').to(PatternElement::TargetReversed),
+        just("#?").to(PatternElement::OptionalBoundary),
+        just('#').to(PatternElement::Boundary),
+        just('_').to(PatternElement::Gap),
+        just('.').to(PatternElement::SyllableBoundary),
     ));
 
     choice((
+        negative_lookahead,
+        negative_lookbehind,
         optional_non_greedy,
         optional,
         wildcard,
         repeat_wild,
         repeat_n,
+        repeat_range,
+        repeat_count,
+        predicate_ref,
         null_category,
+        negated_category,
         cat_ref,
         category,
         simple,
-        text().map(PatternElement::Text),
+        pattern_text().map(PatternElement::Text),
     ))
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pattern {
     pub elements: Vec<PatternElement>,
 }
 
+/// The first element of any of the patterns standing for the target, as `%`
+/// and `<` do, including in optional parts and lookarounds.
+fn target_reference<'p>(
+    patterns: impl IntoIterator<Item = &'p Pattern>,
+) -> Option<&'p PatternElement> {
+    use PatternElement::*;
+
+    patterns.into_iter().find_map(|pattern| {
+        pattern.elements.iter().find_map(|element| match element {
+            Target | TargetReversed => Some(element),
+            Optional(inner)
+            | OptionalNonGreedy(inner)
+            | NegativeLookahead(inner)
+            | NegativeLookbehind(inner) => target_reference([inner]),
+            _ => None,
+        })
+    })
+}
+
+/// The error for an element standing for the target outside of a change,
+/// where there's no target yet for it to stand for.
+fn misplaced_target_reference(element: &PatternElement) -> String {
+    format!("`{element}` stands for the target, so it can only be used in a change")
+}
+
+#[must_use]
 pub fn pattern<'src>() -> impl Parser<'src, &'src str, Pattern, E<'src>> {
     recursive(|pat| {
         pattern_element(pat)
             .repeated()
             .collect::<Vec<PatternElement>>()
+            .validate(|elements, span, emitter| {
+                // a repetition repeats the element before it, so it needs one
+                let dangling = elements.iter().enumerate().any(|(i, element)| {
+                    element.is_repetition() && (i == 0 || elements[i - 1].is_repetition())
+                });
+                if dangling {
+                    emitter.emit(Rich::custom(
+                        span,
+                        "a repetition must follow the element it repeats",
+                    ));
+                }
+                elements
+            })
             .map(|elements| Pattern { elements })
             .boxed() // required to avoid an evil type error
     })
@@ -231,6 +495,42 @@ mod pattern_tests {
         let cases = [
             ("a", vec![Text(String::from("a"))]),
             ("*", vec![Wildcard(Greedy)]),
+            ("a#?", vec![Text(String::from("a")), OptionalBoundary]),
+            ("_#", vec![Gap, Boundary]),
+            (
+                "a{2,3}",
+                vec![
+                    Text(String::from("a")),
+                    RepeatRange {
+                        min: 2,
+                        max: Some(3),
+                    },
+                ],
+            ),
+            (
+                "a{0,}",
+                vec![Text(String::from("a")), RepeatRange { min: 0, max: None }],
+            ),
+            (
+                "a#b",
+                vec![Text(String::from("a")), Boundary, Text(String::from("b"))],
+            ),
+            (
+                "[V](:)",
+                vec![
+                    CatRef(String::from("V")),
+                    Optional(super::Pattern {
+                        elements: vec![Text(String::from(":"))],
+                    }),
+                ],
+            ),
+            (
+                "[!a,[V]]",
+                vec![NegatedCategory(vec![
+                    super::CatOrEl::El(String::from("a")),
+                    super::CatOrEl::Cat(String::from("V")),
+                ])],
+            ),
         ];
 
         for (input, expected) in cases {
@@ -241,34 +541,126 @@ mod pattern_tests {
             assert_eq!(actual, Some(expected));
         }
     }
+
+    #[test]
+    fn dangling_repetition() {
+        for input in ["{3}", "a{2}{3}", "{*}a", "{1,2}a"] {
+            let (_, errs) = super::pattern().parse(input).into_output_errors();
+            assert!(!errs.is_empty(), "{input} should not parse cleanly");
+        }
+    }
+
+    #[test]
+    fn backwards_repeat_range() {
+        let (_, errs) = super::pattern().parse("a{3,2}").into_output_errors();
+        assert!(errs.iter().any(|e| e
+            .to_string()
+            .contains("maximum can't be less than its minimum")));
+    }
 }
 
-#[derive(Debug, Clone, Default)]
-struct Change {
-    pattern: Pattern,
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Change {
+    pub pattern: Pattern,
+    /// Exceptions to only this change, written right after it, as in the
+    /// `!_#` of `a > e!_#, o`. Each `!` starts another.
+    pub exception: Vec<EnvironmentGroup>,
 }
 
-fn change<'src>() -> impl Parser<'src, &'src str, Change, E<'src>> {
-    pattern().map(|pattern| Change { pattern })
+pub(crate) fn change<'src>() -> impl Parser<'src, &'src str, Change, E<'src>> {
+    let exception = just('!').ignore_then(environment_group());
+
+    let pattern = pattern().validate(|pattern, span, emitter| {
+        let unreplaceable = pattern.elements.iter().find(|e| !e.can_replace());
+        if let Some(element) = unreplaceable {
+            emitter.emit(Rich::custom(
+                span,
+                format!("`{element}` can't be used in a change, which only says what replaces the target"),
+            ));
+        }
+        pattern
+    });
+
+    pattern
+        .then(exception.repeated().collect::<Vec<_>>())
+        .map(|(pattern, exception)| Change { pattern, exception })
 }
 
-/// Groups together environments that are connected via `&`.
-#[derive(Debug, Clone, Default)]
-struct EnvironmentGroup {
-    patterns: Vec<Pattern>,
+/// Groups together environments that are connected via `&`, all of which have
+/// to hold for the group to.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EnvironmentGroup {
+    pub patterns: Vec<Pattern>,
 }
 
-fn environment_group<'src>() -> impl Parser<'src, &'src str, EnvironmentGroup, E<'src>> {
-    pattern()
+pub(crate) fn environment_group<'src>() -> impl Parser<'src, &'src str, EnvironmentGroup, E<'src>> {
+    let environment = pattern().validate(|pattern, span, emitter| {
+        // the gap splits the environment into what comes before the target
+        // and what comes after it, so there has to be exactly one
+        let gaps = pattern
+            .elements
+            .iter()
+            .filter(|e| **e == PatternElement::Gap)
+            .count();
+        if gaps != 1 {
+            emitter.emit(Rich::custom(
+                span,
+                format!("an environment must have exactly one `_`, found {gaps}"),
+            ));
+        }
+        if let Some(element) = target_reference([&pattern]) {
+            emitter.emit(Rich::custom(span, misplaced_target_reference(element)));
+        }
+        pattern
+    });
+
+    environment
         .separated_by(just('&').padded_by(inline_whitespace()))
         .collect::<Vec<_>>()
         .map(|patterns| EnvironmentGroup { patterns })
 }
-#[derive(Debug, Clone, Default)]
+
+#[cfg(test)]
+mod environment_tests {
+    use super::PatternElement::{Gap, Text};
+    use chumsky::Parser;
+
+    #[test]
+    fn gap() {
+        let cases = [
+            ("_b", vec![Gap, Text(String::from("b"))]),
+            ("a_", vec![Text(String::from("a")), Gap]),
+            (
+                "a_b",
+                vec![Text(String::from("a")), Gap, Text(String::from("b"))],
+            ),
+        ];
+        for (input, expected) in cases {
+            let group = super::environment_group()
+                .parse(input)
+                .into_result()
+                .unwrap();
+            assert_eq!(group.patterns[0].elements, expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn gap_count() {
+        for input in ["ab", "a_b_", "_b & a"] {
+            let (_, errs) = super::environment_group().parse(input).into_output_errors();
+            assert!(!errs.is_empty(), "{input} should not parse cleanly");
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Predicate {
-    change: Vec<Change>,
-    environment: Vec<EnvironmentGroup>,
-    exception: Vec<EnvironmentGroup>,
+    pub change: Vec<Change>,
+    pub environment: Vec<EnvironmentGroup>,
+    pub exception: Vec<EnvironmentGroup>,
 }
 
 fn environments<'src>() -> impl Parser<'src, &'src str, Vec<EnvironmentGroup>, E<'src>> {
@@ -282,7 +674,7 @@ fn environment_clause<'src>() -> impl Parser<'src, &'src str, Vec<EnvironmentGro
         .then(inline_whitespace())
         .ignore_then(environments())
         .or_not()
-        .map(|e| e.unwrap_or_default())
+        .map(Option::unwrap_or_default)
 }
 
 fn exception_clause<'src>() -> impl Parser<'src, &'src str, Vec<EnvironmentGroup>, E<'src>> {
@@ -290,9 +682,27 @@ fn exception_clause<'src>() -> impl Parser<'src, &'src str, Vec<EnvironmentGroup
         .then(inline_whitespace())
         .ignore_then(environments())
         .or_not()
-        .map(|e| e.unwrap_or_default())
+        .map(Option::unwrap_or_default)
+}
+
+/// Parses the environment and exception clauses of a predicate, in either
+/// order, so that `! c_ / _d` is the same as `/ _d ! c_`.
+fn clauses<'src>(
+) -> impl Parser<'src, &'src str, (Vec<EnvironmentGroup>, Vec<EnvironmentGroup>), E<'src>> {
+    let exception_first = just('!')
+        .rewind()
+        .ignore_then(exception_clause())
+        .then_ignore(inline_whitespace())
+        .then(environment_clause())
+        .map(|(exception, environment)| (environment, exception));
+    let environment_first = environment_clause()
+        .then_ignore(inline_whitespace())
+        .then(exception_clause());
+
+    exception_first.or(environment_first)
 }
 
+#[must_use]
 pub fn predicate<'src>() -> impl Parser<'src, &'src str, Predicate, E<'src>> {
     let changes = change()
         .separated_by(just(',').then_ignore(inline_whitespace()))
@@ -302,26 +712,169 @@ pub fn predicate<'src>() -> impl Parser<'src, &'src str, Predicate, E<'src>> {
         .ignore_then(inline_whitespace())
         .ignore_then(changes)
         .then_ignore(inline_whitespace())
-        .then(environment_clause())
-        .then_ignore(inline_whitespace())
-        .then(exception_clause())
-        .map(|((change, environment), exception)| Predicate {
+        .then(clauses())
+        .map(|(change, (environment, exception))| Predicate {
             change,
             environment,
             exception,
         })
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Target {
-    pattern: Pattern,
-    positions: Vec<isize>,
+    /// Alternative patterns to look for, as given by `a, e, o`. Where several
+    /// match at the same place, the longest match is taken, then the pattern
+    /// given first.
+    pub patterns: Vec<Pattern>,
+    /// Which matches of the pattern in a word the rule applies to, as given by
+    /// `@1|3`. Like in SCE, these count from 1, so `@1` is the first match.
+    /// Negative positions count from the end instead, so `@-1` is the last
+    /// match. Positions past the number of matches do nothing. If empty, the
+    /// rule applies to every match.
+    pub positions: Vec<isize>,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Rule {
-    target: Target,
-    predicates: Vec<Predicate>,
+    pub target: Target,
+    pub predicates: Vec<Predicate>,
+    /// A condition on the whole word, checked before looking for the target.
+    pub guard: Option<Guard>,
+    /// For a sporadic rule, the percentage of chance it has of applying at
+    /// each match, as given by a trailing `?30%`, or `?` for 50%.
+    pub sporadic: Option<u8>,
+    /// Whether the rule is reapplied to its own output until it stops changing
+    /// the word, as given by a trailing `repeat`.
+    pub iterative: bool,
+    /// Which way the word is scanned for matches of the target.
+    pub direction: Direction,
+    /// The most times the rule may replace a match of its target in a single
+    /// word, as given by a trailing `max 2`, counting every application of an
+    /// iterative rule. Further matches are left alone.
+    pub max_applications: Option<usize>,
+}
+
+/// Which way a rule scans a word for matches of its target, as given by `rtl`
+/// right after the target for right to left.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Direction {
+    #[default]
+    LeftToRight,
+    RightToLeft,
+}
+
+impl Rule {
+    /// Starts building a rule in code, without going through the parser.
+    #[must_use]
+    pub fn builder() -> RuleBuilder {
+        RuleBuilder::new()
+    }
+}
+
+/// How a guard compares a word's length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Comparison {
+    #[strum(serialize = "<")]
+    Less,
+    #[strum(serialize = "<=")]
+    LessOrEqual,
+    #[strum(serialize = "==")]
+    Equal,
+    #[strum(serialize = "!=")]
+    NotEqual,
+    #[strum(serialize = ">=")]
+    GreaterOrEqual,
+    #[strum(serialize = ">")]
+    Greater,
+}
+
+/// A condition on the length of a word in phones, not counting word
+/// boundaries, e.g. `if len <= 3`. A rule with a guard only applies to words
+/// which satisfy it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Guard {
+    pub comparison: Comparison,
+    pub length: usize,
+}
+
+impl Guard {
+    /// Whether a word of the given length satisfies the guard.
+    #[must_use]
+    pub fn allows(&self, length: usize) -> bool {
+        use Comparison::*;
+
+        match self.comparison {
+            Less => length < self.length,
+            LessOrEqual => length <= self.length,
+            Equal => length == self.length,
+            NotEqual => length != self.length,
+            GreaterOrEqual => length >= self.length,
+            Greater => length > self.length,
+        }
+    }
+}
+
+fn guard<'src>() -> impl Parser<'src, &'src str, Guard, E<'src>> {
+    let comparison = choice((
+        just("<="),
+        just(">="),
+        just("=="),
+        just("!="),
+        just("<"),
+        just(">"),
+    ))
+    .try_map(|s, span| {
+        Comparison::from_str(s)
+            .map_err(|e| Rich::custom(span, format!("couldn't parse comparison: {e}")))
+    });
+
+    let length = digits(10).slice().try_map(|t: &str, span| {
+        t.parse::<usize>()
+            .map_err(|_| Rich::custom(span, "couldn't parse length"))
+    });
+
+    just("if")
+        .ignore_then(just("len").padded_by(inline_whitespace()))
+        .ignore_then(comparison)
+        .then_ignore(inline_whitespace())
+        .then(length)
+        .map(|(comparison, length)| Guard { comparison, length })
+}
+
+/// Parses the flag marking a rule as sporadic, `?` or `?30%`, into the
+/// percentage of chance it has of applying.
+fn sporadic<'src>() -> impl Parser<'src, &'src str, u8, E<'src>> {
+    let percent = digits(10)
+        .slice()
+        .try_map(|t: &str, span| match t.parse::<u8>() {
+            Ok(percent) if percent <= 100 => Ok(percent),
+            _ => Err(Rich::custom(span, "a percentage must be at most 100")),
+        })
+        .then_ignore(just('%'));
+
+    just('?').ignore_then(percent.or_not().map(|percent| percent.unwrap_or(50)))
+}
+
+/// Parses the flag capping how many times a rule may apply to a word, `max 2`.
+fn max_applications<'src>() -> impl Parser<'src, &'src str, usize, E<'src>> {
+    just("max")
+        .then(inline_whitespace())
+        .ignore_then(
+            digits(10)
+                .slice()
+                .try_map(|t: &str, span| match t.parse::<usize>() {
+                    Ok(max) if max > 0 => Ok(max),
+                    _ => Err(Rich::custom(
+                        span,
+                        "a rule must be able to apply at least once",
+                    )),
+                }),
+        )
 }
 
 fn predicates<'src>() -> impl Parser<'src, &'src str, Vec<Predicate>, E<'src>> {
@@ -331,6 +884,16 @@ fn predicates<'src>() -> impl Parser<'src, &'src str, Vec<Predicate>, E<'src>> {
         .collect::<Vec<_>>()
 }
 
+/// A predicate without a change, only environments and exceptions, as used by
+/// epenthesis and deletion rules, e.g. the `/ _b` in `+ a / _b`.
+fn bare_predicate<'src>() -> impl Parser<'src, &'src str, Predicate, E<'src>> {
+    clauses().map(|(environment, exception)| Predicate {
+        change: vec![],
+        environment,
+        exception,
+    })
+}
+
 fn target<'src>() -> impl Parser<'src, &'src str, Target, E<'src>> {
     let position_num = just('-')
         .or_not()
@@ -346,46 +909,116 @@ fn target<'src>() -> impl Parser<'src, &'src str, Target, E<'src>> {
     );
 
     pattern()
+        .separated_by(just(',').then_ignore(inline_whitespace()))
+        .at_least(1)
+        .collect::<Vec<_>>()
         .then(position.or_not().map(Option::unwrap_or_default))
-        .map(|(pattern, positions)| Target { pattern, positions })
+        .map(|(patterns, positions)| Target {
+            patterns,
+            positions,
+        })
+}
+
+/// A target, optionally followed by `rtl` to look for it right to left.
+fn directed_target<'src>() -> impl Parser<'src, &'src str, (Target, Direction), E<'src>> {
+    let direction = inline_whitespace()
+        .ignore_then(just("rtl"))
+        .to(Direction::RightToLeft)
+        .or_not()
+        .map(Option::unwrap_or_default);
+
+    target().then(direction)
+}
+
+/// Parses a metathesis, which reverses the target where the environment holds,
+/// so that `~ sk / _#` == `sk > < / _#` swaps a word-final `sk` to `ks`. The
+/// environment is that of the target as a whole, as it is for `<`.
+fn metathesis<'src>() -> impl Parser<'src, &'src str, Rule, E<'src>> {
+    just('~')
+        .ignore_then(directed_target().padded_by(inline_whitespace()))
+        .then(bare_predicate())
+        .map(|((target, direction), predicate)| {
+            let reversed = Change {
+                pattern: Pattern {
+                    elements: vec![PatternElement::TargetReversed],
+                },
+                ..Change::default()
+            };
+            Rule {
+                target,
+                predicates: vec![Predicate {
+                    change: vec![reversed],
+                    ..predicate
+                }],
+                direction,
+                ..Rule::default()
+            }
+        })
 }
 
 fn rule<'src>() -> impl Parser<'src, &'src str, Rule, E<'src>> {
-    let rule = target()
+    let rule = directed_target()
         .then_ignore(inline_whitespace())
         .then(predicates())
-        .map(|(target, predicates)| Rule { target, predicates });
+        .map(|((target, direction), predicates)| Rule {
+            target,
+            predicates,
+            direction,
+            ..Rule::default()
+        })
+        .validate(|rule, span, emitter| {
+            // each target is paired with the change in the same place, unless
+            // there's only one change, which all of them share
+            let targets = rule.target.patterns.len();
+            let paired = |predicate: &Predicate| {
+                targets == 1 || predicate.change.len() == 1 || predicate.change.len() == targets
+            };
+            if !rule.predicates.iter().all(paired) {
+                emitter.emit(Rich::custom(
+                    span,
+                    format!("a rule with {targets} targets needs either 1 or {targets} changes"),
+                ));
+            }
+            rule
+        });
 
     // yes, epenthesis can just have an arbitrary predicate. no, i have no clue why
     // see: application of `+ a > b / c` to words `ac`, `ab` results in `aaaca`, `aaaba`
     let epenthesis = just('+')
-        .ignore_then(target().padded_by(inline_whitespace()))
-        .then(predicates())
-        .map(|(target, mut predicates)| {
+        .ignore_then(directed_target().padded_by(inline_whitespace()))
+        .then(predicates().or(bare_predicate().map(|p| vec![p])))
+        .map(|((target, direction), mut predicates)| {
             // set the target to null, and move the target to the change
             // such that `+ a / _b` == `[] > a / _b`
 
             let null_target = Target {
-                pattern: Pattern {
+                patterns: vec![Pattern {
                     elements: vec![PatternElement::Category(vec![])],
-                },
+                }],
                 positions: target.positions,
             };
 
-            predicates[0].change = vec![Change {
-                pattern: target.pattern,
-            }];
+            predicates[0].change = target
+                .patterns
+                .into_iter()
+                .map(|pattern| Change {
+                    pattern,
+                    ..Change::default()
+                })
+                .collect();
 
             Rule {
                 target: null_target,
                 predicates,
+                direction,
+                ..Rule::default()
             }
         });
 
     let deletion = just('-')
-        .ignore_then(target().padded_by(inline_whitespace()))
-        .then(predicates())
-        .map(|(target, predicates)| {
+        .ignore_then(directed_target().padded_by(inline_whitespace()))
+        .then(predicates().or(bare_predicate().map(|p| vec![p])))
+        .map(|((target, direction), predicates)| {
             // set change to null such that `- a / _b` == `a > [] / _b`
 
             let predicates = predicates
@@ -395,6 +1028,7 @@ fn rule<'src>() -> impl Parser<'src, &'src str, Rule, E<'src>> {
                         pattern: Pattern {
                             elements: vec![PatternElement::Category(vec![])],
                         },
+                        ..Change::default()
                     }];
                     Predicate {
                         change: null_change,
@@ -404,30 +1038,284 @@ fn rule<'src>() -> impl Parser<'src, &'src str, Rule, E<'src>> {
                 })
                 .collect();
 
-            Rule { target, predicates }
+            Rule {
+                target,
+                predicates,
+                direction,
+                ..Rule::default()
+            }
         });
 
-    choice((rule, epenthesis, deletion))
+    // a metathesis is tried last, as `~` is also an ordinary phone
+    choice((rule, epenthesis, deletion, metathesis()))
+        .validate(|rule, span, emitter| {
+            if let Some(element) = target_reference(&rule.target.patterns) {
+                emitter.emit(Rich::custom(span, misplaced_target_reference(element)));
+            }
+            rule
+        })
+        .then(inline_whitespace().ignore_then(guard()).or_not())
+        .then(inline_whitespace().ignore_then(sporadic()).or_not())
+        .then(
+            inline_whitespace()
+                .ignore_then(just("repeat"))
+                .or_not()
+                .map(|flag| flag.is_some()),
+        )
+        .then(inline_whitespace().ignore_then(max_applications()).or_not())
+        .map(
+            |((((rule, guard), sporadic), iterative), max_applications)| Rule {
+                guard,
+                sporadic,
+                iterative,
+                max_applications,
+                ..rule
+            },
+        )
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ASTElement {
     Rule(Rule),
     CatEdit(CategoryEdit),
+    /// The polygraphs to parse words with (`graphs: sh, ts, tsh`).
+    Graphs(Vec<String>),
+    /// The separators to parse words with (`separator: ', .`).
+    Separator(Vec<String>),
+    /// A named point in the rules, where the words can be recorded
+    /// (`checkpoint "West Germanic"`).
+    Checkpoint(String),
+    /// The start of a named stage of the rules, which lasts until the next one
+    /// (`stage: west-germanic`).
+    Stage(String),
+    /// Words whose derivations to trace, as they're given (`trace: hund, katt`).
+    Trace(Vec<String>),
+}
+
+/// Parses the `graphs:` and `separator:` directives, which declare how to split
+/// words into phones in the rule file itself, and `checkpoint` and `stage:`
+/// directives.
+fn directive<'src>() -> impl Parser<'src, &'src str, ASTElement, E<'src>> {
+    let value = || {
+        any()
+            .and_is(whitespace().at_least(1).not())
+            .and_is(just(',').not())
+            .repeated()
+            .at_least(1)
+            .collect::<String>()
+    };
+
+    let values = || {
+        value()
+            .separated_by(just(',').then(inline_whitespace()))
+            .at_least(1)
+            .collect::<Vec<_>>()
+    };
+
+    let graphs = just("graphs:")
+        .then(inline_whitespace())
+        .ignore_then(values())
+        .map(ASTElement::Graphs);
+
+    let separator = just("separator:")
+        .then(inline_whitespace())
+        .ignore_then(values())
+        .map(ASTElement::Separator);
+
+    let checkpoint = just("checkpoint")
+        .then(inline_whitespace())
+        .ignore_then(
+            none_of("\"\r\n")
+                .repeated()
+                .collect::<String>()
+                .delimited_by(just('"'), just('"')),
+        )
+        .map(ASTElement::Checkpoint);
+
+    let stage = just("stage:")
+        .then(inline_whitespace())
+        .ignore_then(value())
+        .map(ASTElement::Stage);
+
+    let trace = just("trace:")
+        .then(inline_whitespace())
+        .ignore_then(values())
+        .map(ASTElement::Trace);
+
+    choice((graphs, separator, checkpoint, stage, trace))
 }
 
 pub fn ast_element<'src>() -> impl Parser<'src, &'src str, ASTElement, E<'src>> {
     choice((
+        directive(),
         rule().map(ASTElement::Rule),
         cat_edit().map(ASTElement::CatEdit),
     ))
 }
 
+/// Parses a `table:` directive, a compact way of writing many unconditional
+/// one-to-one substitutions as `from:to` pairs, e.g. `table: a:á b:b́ c:ĉ`.
+///
+/// Each pair becomes its own rule, so the substitutions apply one after the
+/// other, exactly like writing `a > á`, `b > b́`, and so on on separate lines.
+#[must_use]
+pub fn table<'src>() -> impl Parser<'src, &'src str, Vec<Rule>, E<'src>> {
+    let side = || {
+        none_of(CONTROL_CHARACTERS)
+            .and_is(just(':').not())
+            .and_is(whitespace().at_least(1).not())
+            .or(escape())
+            .repeated()
+            .at_least(1)
+            .collect::<String>()
+    };
+
+    let pair = side()
+        .then_ignore(just(':'))
+        .then(side())
+        .map(|(from, to)| Rule {
+            target: Target {
+                patterns: vec![Pattern {
+                    elements: vec![PatternElement::Text(from)],
+                }],
+                positions: vec![],
+            },
+            predicates: vec![Predicate {
+                change: vec![Change {
+                    pattern: Pattern {
+                        elements: vec![PatternElement::Text(to)],
+                    },
+                    ..Change::default()
+                }],
+                ..Predicate::default()
+            }],
+            ..Rule::default()
+        });
+
+    just("table:").then(inline_whitespace()).ignore_then(
+        pair.separated_by(inline_space().repeated().at_least(1))
+            .at_least(1)
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Parses a group of rules sharing an environment, e.g. `{ a > b; c > d } / _#`,
+/// which applies both changes only word-finally.
+///
+/// Each rule of the group becomes its own rule, with the shared environment
+/// added to each of its predicates; see [`with_shared_environment`].
+#[must_use]
+pub fn rule_group<'src>() -> impl Parser<'src, &'src str, Vec<Rule>, E<'src>> {
+    let rules = rule()
+        .separated_by(just(';').padded())
+        .at_least(1)
+        .allow_trailing()
+        .collect::<Vec<_>>()
+        .padded()
+        .delimited_by(just('{'), just('}'));
+
+    rules.then_ignore(inline_whitespace()).then(clauses()).map(
+        |(rules, (environment, exception))| {
+            rules
+                .into_iter()
+                .map(|rule| with_shared_environment(rule, &environment, &exception))
+                .collect()
+        },
+    )
+}
+
+/// Adds a shared environment and exception to each predicate of a rule.
+///
+/// A predicate with its own environment only applies where both it and the
+/// shared environment hold, and one is blocked by either its own exceptions or
+/// the shared ones.
+fn with_shared_environment(
+    mut rule: Rule,
+    environment: &[EnvironmentGroup],
+    exception: &[EnvironmentGroup],
+) -> Rule {
+    for predicate in &mut rule.predicates {
+        if predicate.environment.is_empty() {
+            predicate.environment = environment.to_vec();
+        } else if !environment.is_empty() {
+            // (a or b) and (c or d) == (a and c) or (a and d) or ...
+            predicate.environment = predicate
+                .environment
+                .iter()
+                .flat_map(|own| {
+                    environment.iter().map(|shared| EnvironmentGroup {
+                        patterns: [own.patterns.clone(), shared.patterns.clone()].concat(),
+                    })
+                })
+                .collect();
+        }
+        predicate.exception.extend_from_slice(exception);
+    }
+    rule
+}
+
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AST {
+    #[cfg_attr(feature = "serde", serde(with = "spanned"))]
     pub elements: Vec<(ASTElement, SimpleSpan<usize>)>,
 }
+
+/// Serializes the elements of an AST with their spans as `(start, end)` pairs.
+#[cfg(feature = "serde")]
+mod spanned {
+    use super::ASTElement;
+    use chumsky::span::SimpleSpan;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        elements: &[(ASTElement, SimpleSpan<usize>)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        elements
+            .iter()
+            .map(|(element, span)| (element, (span.start, span.end)))
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(ASTElement, SimpleSpan<usize>)>, D::Error> {
+        let elements = Vec::<(ASTElement, (usize, usize))>::deserialize(deserializer)?;
+        Ok(elements
+            .into_iter()
+            .map(|(element, (start, end))| (element, SimpleSpan::new(start, end)))
+            .collect())
+    }
+}
+
+impl AST {
+    /// The source byte range of each rule, paired with the rule's index among
+    /// the rules of this AST.
+    #[must_use]
+    pub fn rule_spans(&self) -> Vec<(usize, Range<usize>)> {
+        self.spans_of(|element| matches!(element, ASTElement::Rule(_)))
+    }
+
+    /// The source byte range of each category edit, paired with the edit's
+    /// index among the category edits of this AST.
+    #[must_use]
+    pub fn cat_edit_spans(&self) -> Vec<(usize, Range<usize>)> {
+        self.spans_of(|element| matches!(element, ASTElement::CatEdit(_)))
+    }
+
+    fn spans_of(&self, filter: impl Fn(&ASTElement) -> bool) -> Vec<(usize, Range<usize>)> {
+        self.elements
+            .iter()
+            .filter(|(element, _)| filter(element))
+            .enumerate()
+            .map(|(i, (_, span))| (i, span.into_range()))
+            .collect()
+    }
+}
 #[test]
 fn pattern_test() {
     use self::Wildcard::*;
@@ -444,33 +1332,409 @@ fn pattern_test() {
     }
 }
 
+/// Parses a standalone inventory file, which may only define and edit
+/// categories.
+#[must_use]
+pub fn categories<'src>() -> impl Parser<'src, &'src str, Vec<CategoryEdit>, E<'src>> {
+    ast().validate(|ast, _, emitter| {
+        ast.elements
+            .into_iter()
+            .filter_map(|(element, span)| {
+                if let ASTElement::CatEdit(edit) = element {
+                    return Some(edit);
+                }
+                emitter.emit(Rich::custom(
+                    span,
+                    "an inventory file can only define categories",
+                ));
+                None
+            })
+            .collect()
+    })
+}
+
+#[must_use]
 pub fn ast<'src>() -> impl Parser<'src, &'src str, AST, E<'src>> {
-    let comment = just("//")
+    let line_comment = just("//")
         .then(any().and_is(newline().not()).repeated())
-        .then(newline())
-        .padded();
-    ast_element()
-        .map_with_span(|e, span| (e, span))
-        .padded_by(comment.repeated())
+        .ignored();
+    // block comments don't nest, so the first `*/` always ends the comment
+    let block_comment = just("/*")
+        .ignore_then(any().and_is(just("*/").not()).repeated())
+        .ignore_then(just("*/").or_not())
+        .validate(|end, span, emitter| {
+            if end.is_none() {
+                emitter.emit(Rich::custom(
+                    span,
+                    "unterminated block comment, expected a closing `*/`",
+                ));
+            }
+        });
+    let comment = line_comment.or(block_comment);
+    // elements are separated by line breaks or `;`, with any comments between
+    // them
+    let separator = choice((newline(), just(';').ignored(), comment))
         .padded_by(inline_whitespace())
-        .separated_by(newline().repeated().at_least(1))
+        .repeated()
+        .at_least(1);
+    let elements = choice((
+        table().map(|rules| rules.into_iter().map(ASTElement::Rule).collect()),
+        rule_group().map(|rules| rules.into_iter().map(ASTElement::Rule).collect()),
+        ast_element().map(|e| vec![e]),
+    ));
+
+    elements
+        .map_with_span(|elements: Vec<_>, span| {
+            elements.into_iter().map(|e| (e, span)).collect::<Vec<_>>()
+        })
+        .padded_by(inline_whitespace())
+        .separated_by(separator)
+        .allow_leading()
+        .allow_trailing()
         .collect::<Vec<_>>()
         .recover_with(skip_then_retry_until(any().ignored(), end()))
-        .map(|elements| AST { elements })
+        .map(|elements| AST {
+            elements: elements.into_iter().flatten().collect(),
+        })
+}
+
+#[cfg(test)]
+mod ast_tests {
+    use chumsky::Parser;
+
+    fn elements(source: &str) -> Vec<super::ASTElement> {
+        super::ast()
+            .parse(source)
+            .into_output()
+            .unwrap()
+            .elements
+            .into_iter()
+            .map(|(element, _)| element)
+            .collect()
+    }
+
+    #[test]
+    fn builder() {
+        use super::{ASTElement, Rule};
+
+        let rule = Rule::builder()
+            .target("a")
+            .position(-1)
+            .change("e")
+            .environment("_b")
+            .environment("c_")
+            .exception("_bb")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            vec![ASTElement::Rule(rule)],
+            elements("a@-1 > e / _b, c_ ! _bb")
+        );
+    }
+
+    #[test]
+    fn semicolons() {
+        let cases = [
+            ("a > b; c > d", "a > b\nc > d"),
+            ("a > b;c > d;", "a > b\nc > d"),
+            (
+                "V = a, e; [V] > i / _#\nc > d;\n",
+                "V = a, e\n[V] > i / _#\nc > d",
+            ),
+            (
+                "a > b; // a comment\n{ c > d; e > f } / _#; g > h",
+                "a > b\n{ c > d; e > f } / _#\ng > h",
+            ),
+        ];
+        for (semicolons, newlines) in cases {
+            assert_eq!(elements(semicolons), elements(newlines), "{semicolons}");
+        }
+        assert_eq!(elements("a > b; c > d").len(), 2);
+    }
+
+    #[test]
+    fn clause_order() {
+        let cases = [
+            ("a > b ! c_ / _d", "a > b / _d ! c_"),
+            ("a > b ! c_, _e / _d & f_", "a > b / _d & f_ ! c_, _e"),
+            ("a > b ! c_", "a > b ! c_"),
+            ("+ a ! c_ / _d", "+ a / _d ! c_"),
+            ("- a ! c_ / _d", "- a / _d ! c_"),
+            ("~ sk ! a_ / _#", "sk > < / _# ! a_"),
+            ("{ a > b; c > d } ! e_ / _#", "{ a > b; c > d } / _# ! e_"),
+        ];
+        for (exception_first, environment_first) in cases {
+            assert_eq!(
+                elements(exception_first),
+                elements(environment_first),
+                "{exception_first}"
+            );
+        }
+
+        let [super::ASTElement::Rule(rule)] = &elements("a > b ! c_ / _d")[..] else {
+            panic!("should parse to a single rule");
+        };
+        assert_eq!(rule.predicates[0].environment.len(), 1);
+        assert_eq!(rule.predicates[0].exception.len(), 1);
+
+        let (_, errs) = super::ast().parse("a > b ! c_ ! _d").into_output_errors();
+        assert!(!errs.is_empty());
+    }
+
+    #[test]
+    fn line_continuation() {
+        let cases = [
+            ("a > b /\\\n  _c", "a > b / _c"),
+            ("a > b / _c, \\\n  _d ! e_", "a > b / _c, _d ! e_"),
+            ("a > b / _c,\\  \r\n_d\nc > d", "a > b / _c, _d\nc > d"),
+            ("V = a, \\\n e", "V = a, e"),
+        ];
+        for (continued, single) in cases {
+            assert_eq!(elements(continued), elements(single), "{continued:?}");
+        }
+
+        let (_, errs) = super::ast().parse("a > b \\").into_output_errors();
+        assert!(errs
+            .iter()
+            .any(|e| e.to_string().contains("must be followed by a line break")));
+    }
+
+    #[test]
+    fn block_comment() {
+        let cases = [
+            "A = a,b\n/* a comment */\nB = c,d",
+            "A = a,b\n/* a comment\nover /* several\nlines */\nB = c,d",
+            "A = a,b\n/**/\n// and a line comment\nB = c,d",
+        ];
+        for source in cases {
+            assert_eq!(elements(source), elements("A = a,b\nB = c,d"), "{source}");
+        }
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        let (_, errs) = super::ast()
+            .parse("A = a,b\n/* a comment\nB = c,d")
+            .into_output_errors();
+        assert!(errs
+            .iter()
+            .any(|e| e.to_string().contains("unterminated block comment")));
+    }
+
+    #[test]
+    fn directives() {
+        use super::ASTElement::*;
+
+        assert_eq!(
+            elements("graphs: sh, ts, tsh\nseparator: '"),
+            vec![
+                Graphs(vec![
+                    String::from("sh"),
+                    String::from("ts"),
+                    String::from("tsh")
+                ]),
+                Separator(vec![String::from("'")])
+            ]
+        );
+        assert_eq!(
+            elements("separator: ', ."),
+            vec![Separator(vec![String::from("'"), String::from(".")])]
+        );
+        assert_eq!(
+            elements("checkpoint \"West Germanic\""),
+            vec![Checkpoint(String::from("West Germanic"))]
+        );
+        assert_eq!(
+            elements("stage: west-germanic"),
+            vec![Stage(String::from("west-germanic"))]
+        );
+        assert_eq!(
+            elements("trace: hund, katt"),
+            vec![Trace(vec![String::from("hund"), String::from("katt")])]
+        );
+    }
+
+    #[test]
+    fn inventory() {
+        let (edits, errs) = super::categories()
+            .parse("V = a, e\nV += i")
+            .into_output_errors();
+        assert!(errs.is_empty());
+        assert_eq!(edits.map(|edits| edits.len()), Some(2));
+
+        let (_, errs) = super::categories()
+            .parse("V = a, e\na > e")
+            .into_output_errors();
+        assert!(errs
+            .iter()
+            .any(|e| e.to_string().contains("can only define categories")));
+    }
+
+    #[test]
+    fn mismatched_targets() {
+        let (_, errs) = super::ast().parse("a, b, c > x, y").into_output_errors();
+        assert!(!errs.is_empty());
+    }
+
+    #[test]
+    fn target_reference_outside_change() {
+        let sources = [
+            "% > b",
+            "a > b / _%",
+            "a > b ! (%)_",
+            "a > b!_%",
+            "< > b",
+            "a > b / _<",
+            "a > b / (?!<)_",
+            "+ %",
+        ];
+        for source in sources {
+            let (_, errs) = super::ast().parse(source).into_output_errors();
+            let expected = source != "+ %";
+            assert_eq!(!errs.is_empty(), expected, "{source}: {errs:?}");
+        }
+        let errors = crate::parse("a > b / _%").unwrap_err();
+        assert!(
+            errors[0].message.contains("can only be used in a change"),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn unreplaceable_change() {
+        for source in [
+            "a > b*",
+            "a > (b)",
+            "a > _",
+            "a > .",
+            "a > [!b]",
+            "a > b{1,2}",
+        ] {
+            let (_, errs) = super::ast().parse(source).into_output_errors();
+            assert!(!errs.is_empty(), "{source} should not parse cleanly");
+        }
+        let errors = crate::parse("a > b*").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "`*` can't be used in a change, which only says what replaces the target"
+        );
+    }
+
+    #[test]
+    fn guard() {
+        use super::{ASTElement, Comparison, Guard};
+
+        let cases = [
+            ("a > e if len <= 3", Comparison::LessOrEqual, 3),
+            ("+ x / _# if len<2", Comparison::Less, 2),
+            ("- a if len != 10", Comparison::NotEqual, 10),
+        ];
+        for (source, comparison, length) in cases {
+            let [ASTElement::Rule(rule)] = &elements(source)[..] else {
+                panic!("{source} should parse to a single rule");
+            };
+            assert_eq!(rule.guard, Some(Guard { comparison, length }), "{source}");
+        }
+    }
+
+    #[test]
+    fn sporadic() {
+        use super::ASTElement;
+
+        let cases = [
+            ("a > e ?", Some(50)),
+            ("a > e / _# ?30%", Some(30)),
+            ("- a if len > 2 ?100%", Some(100)),
+            ("a > e", None),
+        ];
+        for (source, sporadic) in cases {
+            let [ASTElement::Rule(rule)] = &elements(source)[..] else {
+                panic!("{source} should parse to a single rule");
+            };
+            assert_eq!(rule.sporadic, sporadic, "{source}");
+        }
+
+        let (_, errs) = super::ast().parse("a > e ?101%").into_output_errors();
+        assert!(!errs.is_empty());
+    }
+
+    #[test]
+    fn max_applications() {
+        use super::ASTElement;
+
+        let cases = [
+            ("a > e max 2", Some(2)),
+            ("a > e / _# ?30% repeat max 10", Some(10)),
+            ("a > e repeat", None),
+        ];
+        for (source, max) in cases {
+            let [ASTElement::Rule(rule)] = &elements(source)[..] else {
+                panic!("{source} should parse to a single rule");
+            };
+            assert_eq!(rule.max_applications, max, "{source}");
+        }
+
+        let (_, errs) = super::ast().parse("a > e max 0").into_output_errors();
+        assert!(!errs.is_empty());
+    }
+
+    #[test]
+    fn rule_group() {
+        let cases = [
+            ("{ a > b; c > d } / _#", "a > b / _#\nc > d / _#"),
+            (
+                "{a > b;\n c > d;} / _# ! b_",
+                "a > b / _# ! b_\nc > d / _# ! b_",
+            ),
+            ("{ a > b / _c, _d } / e_", "a > b / _c & e_, _d & e_"),
+        ];
+        for (grouped, separate) in cases {
+            assert_eq!(elements(grouped), elements(separate), "{grouped}");
+        }
+    }
+
+    #[test]
+    fn table() {
+        let table = elements("table: a:á b:b́ c:ĉ");
+        assert_eq!(table.len(), 3);
+        assert_eq!(table, elements("a > á\nb > b́\nc > ĉ"));
+    }
+
+    #[test]
+    fn spans() {
+        let source = "a > b\nC = x,y\ncd > e";
+        let ast = super::ast().parse(source).into_output().unwrap();
+
+        assert_eq!(ast.rule_spans(), vec![(0, 0..5), (1, 14..20)]);
+        assert_eq!(ast.cat_edit_spans(), vec![(0, 6..13)]);
+        assert_eq!(&source[14..20], "cd > e");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let source = "V = a, [C], []\n[V]{*} > %% / #_(?!k) ! _. if len < 4\ncheckpoint \"Old\"";
+        let ast = super::ast().parse(source).into_output().unwrap();
+
+        let json = serde_json::to_string(&ast).unwrap();
+        let round_tripped: super::AST = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(round_tripped, ast);
+        assert_eq!(round_tripped.rule_spans(), ast.rule_spans());
+    }
 }
 
 #[cfg(test)]
-mod bench {
+pub(crate) mod bench {
     extern crate test;
     use chumsky::Parser;
     use test::Bencher;
 
-    #[bench]
-    fn ast_bench(b: &mut Bencher) {
-        // saxonish sound changes
-        // https://conworkshop.com/view_language.php?l=sxs
-        // shoutout!
-        let input = r#"
+    // saxonish sound changes
+    // https://conworkshop.com/view_language.php?l=sxs
+    // shoutout!
+    pub(crate) const SAXONISH: &str = r#"
     N=m,n
     T=p,t,k
     D=b,d,g
@@ -516,6 +1780,9 @@ mod bench {
     ą, ę, ǫ > ɔ, i, u
     ą, ę, į, ǫ, ų > a, e, i, o, u
 "#;
-        b.iter(|| crate::parse::ast().parse(input).into_output_errors());
+
+    #[bench]
+    fn ast_bench(b: &mut Bencher) {
+        b.iter(|| crate::parse::ast().parse(SAXONISH).into_output_errors());
     }
 }
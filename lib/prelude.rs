@@ -0,0 +1,27 @@
+//! The types and functions most uses of the crate need, so that they can all
+//! be brought in at once.
+//!
+//! ```
+//! use sce::prelude::*;
+//!
+//! let ast = parse("V = a, e\n[V] > i / _t").unwrap();
+//! let (words, _) = apply(ast, vec![String::from("kat")], vec![], vec![]).unwrap();
+//! assert_eq!(words, vec!["kit"]);
+//! ```
+
+pub use crate::{
+    apply::{
+        apply, apply_from_state, apply_stream, apply_trace, apply_traced, apply_with_options,
+        ApplyError, ApplyOptions, Category, InterpreterState, Warning,
+    },
+    builder::{CategoryEditBuilder, RuleBuilder},
+    common::Wildcard,
+    engine::Engine,
+    parse,
+    parse::{Pattern, Rule, AST},
+    report::ParseError,
+    word::Word,
+};
+
+#[cfg(feature = "rayon")]
+pub use crate::apply::apply_parallel;
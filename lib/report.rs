@@ -0,0 +1,313 @@
+//! Turns parse errors into messages and reports for people to read, rather
+//! than the debug output of the parser's errors.
+
+use std::{fmt::Display, io::Write, ops::Range};
+
+use ariadne::{sources, Label, Report, ReportKind};
+use chumsky::error::{Rich, RichPattern, RichReason};
+
+/// How a character is written in a message, naming the whitespace ones which
+/// wouldn't show up otherwise.
+fn describe_char(c: char) -> String {
+    match c {
+        ' ' => String::from("a space"),
+        '\t' => String::from("a tab"),
+        '\n' | '\r' => String::from("a line break"),
+        c => format!("`{c}`"),
+    }
+}
+
+fn describe_reason(reason: &RichReason<'_, char>) -> String {
+    match reason {
+        RichReason::ExpectedFound { expected, found } => {
+            let mut expected: Vec<_> = expected
+                .iter()
+                .map(|pattern| match pattern {
+                    RichPattern::Token(c) => describe_char(**c),
+                    RichPattern::Label(label) => (*label).to_string(),
+                    RichPattern::EndOfInput => String::from("the end of the input"),
+                })
+                .collect();
+            expected.sort();
+            expected.dedup();
+            let found = found.as_deref().map_or_else(
+                || String::from("the end of the input"),
+                |c| describe_char(*c),
+            );
+
+            match &expected[..] {
+                [] => format!("found {found}, which wasn't expected"),
+                [only] => format!("expected {only} but found {found}"),
+                _ => format!("expected one of {} but found {found}", expected.join(", ")),
+            }
+        }
+        RichReason::Custom(message) => message.clone(),
+        RichReason::Many(reasons) => reasons
+            .iter()
+            .map(describe_reason)
+            .collect::<Vec<_>>()
+            .join("; "),
+    }
+}
+
+/// A message saying what went wrong in a parse error, such as "expected one of
+/// `>`, `=` but found `x`", or its own message for a custom error.
+#[must_use]
+pub fn describe(error: &Rich<'_, char>) -> String {
+    describe_reason(error.reason())
+}
+
+/// The line and column of a byte offset into the source, both counting from
+/// 1. Columns count characters rather than bytes, so that `ā` is one column.
+#[must_use]
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..source.floor_char_boundary(offset)];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+}
+
+/// A parse error, along with where in the source it was found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParseError {
+    /// What went wrong; see [`describe`].
+    pub message: String,
+    /// The character found where something else was expected, if any.
+    pub found: Option<char>,
+    /// The bytes of the source the error covers.
+    pub span: Range<usize>,
+    /// The line the error starts on, counting from 1.
+    pub line: usize,
+    /// The column the error starts at, counting characters from 1.
+    pub column: usize,
+}
+
+impl ParseError {
+    /// Locates an error of the parser in the source it was found in.
+    #[must_use]
+    pub fn new(source: &str, error: &Rich<'_, char>) -> Self {
+        let span = error.span().into_range();
+        let (line, column) = line_column(source, span.start);
+        ParseError {
+            message: describe(error),
+            found: error.found().copied(),
+            span,
+            line,
+            column,
+        }
+    }
+
+    /// An error of our own, rather than the parser's, covering `span`.
+    fn custom(source: &str, span: Range<usize>, message: String, found: Option<char>) -> Self {
+        let (line, column) = line_column(source, span.start);
+        ParseError {
+            message,
+            found,
+            span,
+            line,
+            column,
+        }
+    }
+}
+
+/// The bracket closing `open`.
+fn closing(open: char) -> char {
+    match open {
+        '(' => ')',
+        '[' => ']',
+        _ => '}',
+    }
+}
+
+/// Finds the brackets in the source which are never closed, or close nothing,
+/// since the parser's errors for them are found wherever it gives up, often
+/// far from the bracket itself. An unclosed bracket's error points at where it
+/// was opened.
+///
+/// Escaped brackets, comments and checkpoint names are skipped. `<` isn't a
+/// bracket in SCE, but the reversed target, so it doesn't need closing.
+#[must_use]
+pub fn unbalanced_delimiters(source: &str) -> Vec<ParseError> {
+    let unclosed = |(offset, open): (usize, char)| {
+        let message = format!("unclosed `{open}`, expected a matching `{}`", closing(open));
+        ParseError::custom(source, offset..offset + 1, message, None)
+    };
+
+    let mut errors = vec![];
+    let mut open: Vec<(usize, char)> = vec![];
+    // where the current element started, to tell whether it's a checkpoint
+    let mut element = 0;
+    let mut chars = source.char_indices().peekable();
+    while let Some((offset, c)) = chars.next() {
+        let rest = &source[offset..];
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '\n' | ';' => element = offset + 1,
+            '/' if rest.starts_with("//") => while chars.next_if(|&(_, c)| c != '\n').is_some() {},
+            '/' if rest.starts_with("/*") => {
+                chars.next();
+                let end = rest[2..]
+                    .find("*/")
+                    .map_or(source.len(), |end| offset + end + 4);
+                while chars.next_if(|&(i, _)| i < end).is_some() {}
+            }
+            '"' if source[element..offset].trim() == "checkpoint" => {
+                while chars.next_if(|&(_, c)| c != '"' && c != '\n').is_some() {}
+                chars.next_if(|&(_, c)| c == '"');
+            }
+            '(' | '[' | '{' => open.push((offset, c)),
+            ')' | ']' | '}' => {
+                let Some(matching) = open.iter().rposition(|&(_, open)| closing(open) == c) else {
+                    let opening = match c {
+                        ')' => '(',
+                        ']' => '[',
+                        _ => '{',
+                    };
+                    let message = format!("unmatched `{c}`, with no `{opening}` before it");
+                    errors.push(ParseError::custom(
+                        source,
+                        offset..offset + 1,
+                        message,
+                        Some(c),
+                    ));
+                    continue;
+                };
+                // anything opened since was never closed
+                errors.extend(open.drain(matching..).skip(1).map(unclosed));
+            }
+            _ => {}
+        }
+    }
+    errors.extend(open.into_iter().map(unclosed));
+    errors.sort_by_key(|error| error.span.start);
+    errors
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Writes a report of each of the errors to `writer`, pointing out where in
+/// the source each was found.
+///
+/// ## Errors
+/// Errors if the reports can't be written.
+pub fn write_reports(
+    source: &str,
+    errors: &[ParseError],
+    mut writer: impl Write,
+) -> std::io::Result<()> {
+    // the reports count characters rather than bytes
+    let chars = |offset: usize| source[..source.floor_char_boundary(offset)].chars().count();
+    for error in errors {
+        let label = match error.found {
+            Some(c) => format!("unexpected {}", describe_char(c)),
+            None => error.message.clone(),
+        };
+        let span = chars(error.span.start)..chars(error.span.end);
+        Report::build(ReportKind::Error, String::from("src"), span.start)
+            .with_message(&error.message)
+            .with_label(Label::new((String::from("src"), span)).with_message(label))
+            .finish()
+            .write(sources([(String::from("src"), source)]), &mut writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod report_tests {
+    use chumsky::Parser;
+
+    use crate::parse::ast;
+
+    #[test]
+    fn expected() {
+        let (_, errors) = ast().parse("V = a\nb x c").into_output_errors();
+        let message = super::describe(&errors[0]);
+        assert!(message.starts_with("expected one of "), "{message}");
+        assert!(message.contains("`>`"), "{message}");
+        assert!(message.contains("`=`"), "{message}");
+        assert!(message.ends_with("but found `x`"), "{message}");
+
+        let errors = [super::ParseError::new("V = a\nb x c", &errors[0])];
+        let mut report = vec![];
+        super::write_reports("V = a\nb x c", &errors, &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains(&message), "{report}");
+        assert!(report.contains("unexpected `x`"), "{report}");
+        assert!(!report.contains("ExpectedFound"), "{report}");
+    }
+
+    #[test]
+    fn custom() {
+        let (_, errors) = ast().parse("a > b\n/* x").into_output_errors();
+        assert!(errors
+            .iter()
+            .any(|error| super::describe(error)
+                == "unterminated block comment, expected a closing `*/`"));
+    }
+
+    #[test]
+    fn line_column() {
+        let source = "V = \u{101}, e\n// \u{101}\n\u{101}\u{101} x\n";
+        let (_, errors) = ast().parse(source).into_output_errors();
+        let error = super::ParseError::new(source, &errors[0]);
+        assert_eq!((error.line, error.column), (3, 4));
+        assert_eq!(&source[error.span.clone()], "x");
+        assert!(error.to_string().starts_with("3:4: expected"), "{error}");
+
+        assert_eq!(super::line_column(source, 0), (1, 1));
+        assert_eq!(super::line_column(source, source.len()), (4, 1));
+
+        let mut report = vec![];
+        super::write_reports(source, &[error], &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains("src:3:4"), "{report}");
+    }
+
+    #[test]
+    fn unbalanced_delimiters() {
+        let cases = [
+            ("a > (b / _c", 4, "unclosed `(`"),
+            ("[V > a", 0, "unclosed `[`"),
+            ("a{2 > b", 1, "unclosed `{`"),
+            ("a > b\n{ c > d; e > f / _#", 6, "unclosed `{`"),
+            ("a > [b(c] / _d", 6, "unclosed `(`"),
+            ("a > b) / _c", 5, "unmatched `)`"),
+        ];
+        for (source, offset, message) in cases {
+            let errors = super::unbalanced_delimiters(source);
+            assert_eq!(errors.len(), 1, "{source}: {errors:?}");
+            assert_eq!(errors[0].span.start, offset, "{source}");
+            assert!(
+                errors[0].message.starts_with(message),
+                "{source}: {errors:?}"
+            );
+        }
+
+        let errors = crate::parse("V = a, e\na > (b / _c").unwrap_err();
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert_eq!(
+            errors[0].to_string(),
+            "2:5: unclosed `(`, expected a matching `)`"
+        );
+
+        let balanced = [
+            "\\( > a // (",
+            "a > b /* ( */",
+            "checkpoint \"(\"\na > b",
+            "{ a > (b)?; [V] > c{2} } / _#",
+        ];
+        for source in balanced {
+            assert_eq!(super::unbalanced_delimiters(source), vec![], "{source}");
+        }
+    }
+}
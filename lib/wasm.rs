@@ -0,0 +1,78 @@
+//! Bindings for calling the crate from JavaScript, when it's built for
+//! WebAssembly with the `wasm` feature.
+//!
+//! Errors come back as JavaScript objects rather than panics: a parse error
+//! is an array of `{ message, found, span: { start, end }, line, column }`,
+//! and an error applying the rules is `{ message }`.
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+/// An error which isn't about a particular place in the source.
+#[derive(Serialize)]
+struct Error {
+    message: String,
+}
+
+/// The value as a JavaScript object, or the error converting it.
+fn to_js(value: &impl Serialize) -> JsValue {
+    serde_wasm_bindgen::to_value(value).unwrap_or_else(JsValue::from)
+}
+
+/// Parses rules, returning their syntax tree.
+///
+/// ## Errors
+/// Returns the parse errors, with their spans; see [`crate::report::ParseError`].
+#[wasm_bindgen]
+pub fn parse_rules(src: &str) -> Result<JsValue, JsValue> {
+    crate::parse(src)
+        .map(|ast| to_js(&ast))
+        .map_err(|errors| to_js(&errors))
+}
+
+/// Applies rules to words, split into phones with `graphs` and `separator`,
+/// which may be empty for none.
+///
+/// ## Errors
+/// Returns the parse errors if the rules can't be parsed, or an error if they
+/// can't be applied.
+#[wasm_bindgen]
+pub fn apply(
+    src: &str,
+    words: Vec<String>,
+    graphs: Vec<String>,
+    separator: String,
+) -> Result<Vec<String>, JsValue> {
+    let ast = crate::parse(src).map_err(|errors| to_js(&errors))?;
+    let separators = if separator.is_empty() {
+        vec![]
+    } else {
+        vec![separator]
+    };
+    crate::apply(ast, words, graphs, separators)
+        .map(|(words, _)| words)
+        .map_err(|error| {
+            to_js(&Error {
+                message: error.to_string(),
+            })
+        })
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod wasm_tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn apply() {
+        let src = "V = a, e\n[V] > o / _n";
+        let words = vec![String::from("kan"), String::from("ten"), String::from("ka")];
+        let applied = super::apply(src, words, vec![], String::from("'")).unwrap();
+        assert_eq!(applied, vec!["kon", "ton", "ka"]);
+
+        assert!(super::parse_rules(src).is_ok());
+        let errors = super::parse_rules("a > (b / _c").unwrap_err();
+        let errors: Vec<crate::report::ParseError> =
+            serde_wasm_bindgen::from_value(errors).unwrap();
+        assert_eq!(errors[0].span, 4..5);
+    }
+}
@@ -1,12 +1,26 @@
-use std::{fmt::Display, ops::Range, ops::RangeInclusive};
+use std::{collections::HashMap, convert::Infallible, fmt::Display, ops::Range, str::FromStr};
 
-use crate::parse::{Pattern, PatternElement};
+use crate::{
+    apply::{apply_rule, cat_or_els_to_els, category_members, ApplyError, Category, Predicates},
+    common::Rng,
+    parse::{CatOrEl, Pattern, PatternElement, Rule},
+};
 
 #[derive(Clone, Debug, PartialEq, Default)]
 pub struct Word {
     pub phones: Vec<String>,
     pub graphs: Vec<String>,
-    pub separator: String,
+    /// The strings which separate phones that would otherwise be read as a
+    /// polygraph, of which the first is used when displaying the word.
+    pub separators: Vec<String>,
+    /// The indices of the phones which begin a new syllable, given by
+    /// separating syllables with `.` in the input when it's parsed with
+    /// [`parse_syllabified`]. Word boundaries always break syllables, so they
+    /// needn't be listed.
+    pub syllable_breaks: Vec<usize>,
+    /// Whether phones are compared ignoring case when matching, as graphs were
+    /// when the word was split into phones. The phones keep their own case.
+    pub case_insensitive: bool,
 }
 
 /// A multiple-element match.
@@ -38,71 +52,854 @@ pub enum Match {
     Single(SingleMatch),
 }
 
+impl Match {
+    /// The range of the match in the word.
+    #[must_use]
+    pub fn range(&self) -> Range<usize> {
+        match self {
+            Match::Multiple(m) => m.range.clone(),
+            Match::Single(m) => m.range.clone(),
+        }
+    }
+
+    /// How many times the element before a repetition (`{3}`, `{*}`) was
+    /// matched, or `None` if this isn't the match of a repetition.
+    #[must_use]
+    pub fn repetitions(&self) -> Option<usize> {
+        match self {
+            Match::Multiple(MultipleMatch {
+                element:
+                    PatternElement::RepeatN(_)
+                    | PatternElement::RepeatRange { .. }
+                    | PatternElement::RepeatWild(_),
+                matches,
+                ..
+            }) => Some(matches.len()),
+            _ => None,
+        }
+    }
+}
+
+/// Called with the end index and the matches of each successful match, in order
+/// of preference. Returning `true` accepts the match and stops the search;
+/// returning `false` backtracks into the next alternative.
+type Continuation<'a> = dyn FnMut(usize, Vec<Match>) -> bool + 'a;
+
 impl Word {
+    /// Parses an input string into a word using the given graphs and
+    /// separators; see [`parse`].
+    #[must_use]
+    pub fn new(input: &str, graphs: &[String], separators: &[String]) -> Self {
+        parse(input, graphs.to_vec(), separators.to_vec())
+    }
+
     /// Match a pattern to the phonemes of a word, starting from the given index.
+    /// Category references are resolved using `categories`.
     ///
     /// ## Returns
     /// A vector of matches, or `None` if the pattern does not match.
-    #[allow(clippy::range_plus_one)] // whyyyy is RangeInclusive a different type
-    pub fn match_one(&self, pattern: &Pattern, start_index: usize) -> Option<Vec<Match>> {
-        use crate::parse::PatternElement::*;
+    #[must_use]
+    pub fn match_one(
+        &self,
+        pattern: &Pattern,
+        categories: &HashMap<String, Category>,
+        start_index: usize,
+    ) -> Option<Vec<Match>> {
+        self.match_one_with(pattern, categories, &HashMap::new(), start_index)
+    }
 
-        let mut matches = vec![];
+    /// Like [`Word::match_one`], also resolving predicate references using
+    /// `predicates`.
+    #[must_use]
+    pub fn match_one_with(
+        &self,
+        pattern: &Pattern,
+        categories: &HashMap<String, Category>,
+        predicates: &Predicates,
+        start_index: usize,
+    ) -> Option<Vec<Match>> {
+        let pattern = CompiledPattern::new(pattern, &self.graphs, &self.separators);
+        self.match_compiled(&pattern, categories, predicates, start_index)
+    }
+
+    /// Like [`Word::match_one_with`], with a pattern compiled ahead of time, so
+    /// that matching it at every index doesn't split its text each time.
+    #[must_use]
+    pub fn match_compiled(
+        &self,
+        pattern: &CompiledPattern,
+        categories: &HashMap<String, Category>,
+        predicates: &Predicates,
+        start_index: usize,
+    ) -> Option<Vec<Match>> {
+        let limit = self.phones.len();
+        self.match_compiled_within(pattern, categories, predicates, start_index..limit)
+    }
 
-        let mut index = start_index;
-        let mut last_index = start_index;
+    /// Like [`Word::match_compiled`], only taking a match which starts at
+    /// `range.start` and ends no later than `range.end`, backtracking into
+    /// shorter ones when the preferred match runs past it.
+    #[must_use]
+    pub(crate) fn match_compiled_within(
+        &self,
+        pattern: &CompiledPattern,
+        categories: &HashMap<String, Category>,
+        predicates: &Predicates,
+        range: Range<usize>,
+    ) -> Option<Vec<Match>> {
+        let pattern = &pattern.elements;
 
-        // disgusting
-        let pattern = pattern
-            .elements
-            .iter()
-            .flat_map(|e| match e {
-                Text(t) => {
-                    let elements = into_phones(t.clone(), &self.graphs, &self.separator);
-                    elements.into_iter().map(Text).collect()
+        log::trace!("pattern: {pattern:?}");
+
+        let matcher = Matcher {
+            word: self,
+            categories,
+            predicates,
+        };
+
+        let mut result = None;
+        matcher.match_elements(pattern, 0, range.start, vec![], &mut |end, matches| {
+            if end > range.end {
+                return false;
+            }
+            result = Some(matches);
+            true
+        });
+        result
+    }
+
+    /// Finds every match of a pattern in the word, left to right. At each index
+    /// the pattern matches as it prefers, so a greedy pattern as much as it can,
+    /// and the next match is looked for from where it ends, so matches never
+    /// overlap. An empty match is found at most once at each index, and never
+    /// before the initial word boundary, as when applying rules.
+    #[must_use]
+    pub fn find_all(
+        &self,
+        pattern: &Pattern,
+        categories: &HashMap<String, Category>,
+    ) -> Vec<Vec<Match>> {
+        let mut found = vec![];
+        self.find(pattern, categories, |matches| {
+            found.push(matches);
+            true
+        });
+        found
+    }
+
+    /// The leftmost match of a pattern in the word; see [`Word::find_all`].
+    #[must_use]
+    pub fn find_first(
+        &self,
+        pattern: &Pattern,
+        categories: &HashMap<String, Category>,
+    ) -> Option<Vec<Match>> {
+        let mut first = None;
+        self.find(pattern, categories, |matches| {
+            first = Some(matches);
+            false
+        });
+        first
+    }
+
+    /// Gives `found` each match of the pattern in turn, as [`Word::find_all`]
+    /// finds them, until it returns `false`.
+    fn find(
+        &self,
+        pattern: &Pattern,
+        categories: &HashMap<String, Category>,
+        found: impl FnMut(Vec<Match>) -> bool,
+    ) {
+        let pattern = CompiledPattern::new(pattern, &self.graphs, &self.separators);
+        let predicates = HashMap::new();
+        let match_at = |index| {
+            let matches = self.match_compiled(&pattern, categories, &predicates, index)?;
+            let end = matches.last().map_or(index, |m| m.range().end);
+            Some((index..end, matches))
+        };
+        self.scan(match_at, found);
+    }
+
+    /// Looks for matches left to right, where `match_at` gives the match at an
+    /// index, if any, along with the range it covers. The next match is looked
+    /// for from where each ends, so that they never overlap, and an empty one
+    /// is found at most once at each index, and never before the initial word
+    /// boundary. `found` is given each match in turn, until it returns `false`.
+    pub(crate) fn scan<T>(
+        &self,
+        mut match_at: impl FnMut(usize) -> Option<(Range<usize>, T)>,
+        mut found: impl FnMut(T) -> bool,
+    ) {
+        let mut index = 0;
+        while index < self.phones.len() {
+            match match_at(index).filter(|(range, _)| !self.before_start(range)) {
+                Some((range, next)) => {
+                    if !found(next) {
+                        return;
+                    }
+                    // an empty match would otherwise be found over and over
+                    index = range.end.max(index + 1);
                 }
-                _ => vec![e.clone()],
-            })
-            .collect::<Vec<_>>();
+                None => index += 1,
+            }
+        }
+    }
+
+    /// Whether the range is an empty match before the initial word boundary,
+    /// and so outside the word.
+    pub(crate) fn before_start(&self, range: &Range<usize>) -> bool {
+        range.is_empty() && range.start == 0 && self.phones.first().is_some_and(|p| p == "#")
+    }
+
+    /// Match a pattern to the phonemes of a word, such that the match ends right
+    /// before the given index. Category references are resolved using
+    /// `categories`.
+    ///
+    /// ## Returns
+    /// A vector of matches, or `None` if the pattern does not match.
+    #[must_use]
+    pub fn match_ending_at(
+        &self,
+        pattern: &Pattern,
+        categories: &HashMap<String, Category>,
+        end_index: usize,
+    ) -> Option<Vec<Match>> {
+        self.match_ending_at_with(pattern, categories, &HashMap::new(), end_index)
+    }
+
+    /// Like [`Word::match_ending_at`], also resolving predicate references
+    /// using `predicates`.
+    #[must_use]
+    pub fn match_ending_at_with(
+        &self,
+        pattern: &Pattern,
+        categories: &HashMap<String, Category>,
+        predicates: &Predicates,
+        end_index: usize,
+    ) -> Option<Vec<Match>> {
+        let pattern = CompiledPattern::new(pattern, &self.graphs, &self.separators);
+        self.match_compiled_ending_at(&pattern, categories, predicates, end_index)
+    }
+
+    /// Like [`Word::match_ending_at_with`], with a pattern compiled ahead of
+    /// time.
+    #[must_use]
+    pub fn match_compiled_ending_at(
+        &self,
+        pattern: &CompiledPattern,
+        categories: &HashMap<String, Category>,
+        predicates: &Predicates,
+        end_index: usize,
+    ) -> Option<Vec<Match>> {
+        let matcher = Matcher {
+            word: self,
+            categories,
+            predicates,
+        };
+
+        matcher.match_ending_at(&pattern.elements, end_index)
+    }
 
-        println!("pattern: {pattern:?}");
+    /// Applies a single rule to every place in the word it matches, checking
+    /// its environments and exceptions like [`crate::apply::apply`] does.
+    /// Category references are resolved using `categories`.
+    ///
+    /// A sporadic rule applies as it would with the seed 0.
+    ///
+    /// ## Returns
+    /// The transformed word.
+    ///
+    /// ## Errors
+    /// Errors if the rule can't be applied, as when it refers to a category
+    /// which isn't defined; see [`ApplyError`]. The rule's index in the error
+    /// is 0.
+    pub fn apply_rule(
+        &self,
+        rule: &Rule,
+        categories: &HashMap<String, Category>,
+    ) -> Result<Word, ApplyError> {
+        apply_rule(rule, self, categories, &HashMap::new(), &mut Rng::new(0))
+            .map_err(|e| e.in_rule(0, self))
+    }
+
+    /// Whether a syllable break sits right before the phone at `index`.
+    #[must_use]
+    pub fn is_syllable_break(&self, index: usize) -> bool {
+        let is_boundary = |i: usize| self.phones.get(i).is_some_and(|phone| phone == "#");
+        self.syllable_breaks.contains(&index)
+            || is_boundary(index)
+            || index.checked_sub(1).is_some_and(is_boundary)
+    }
+
+    /// Whether two phones are the same, ignoring case if the word is matched
+    /// case-insensitively.
+    pub(crate) fn same_phone(&self, a: &str, b: &str) -> bool {
+        a == b || self.case_insensitive && a.to_lowercase() == b.to_lowercase()
+    }
+
+    /// Whether the phone at `index` is the same as the one right before it.
+    fn repeats_previous(&self, index: usize) -> bool {
+        let previous = index.checked_sub(1).and_then(|i| self.phones.get(i));
+        match (self.phones.get(index), previous) {
+            (Some(phone), Some(previous)) => self.same_phone(phone, previous),
+            _ => false,
+        }
+    }
+
+    /// Whether two runs of phones are the same, like [`Word::same_phone`].
+    pub(crate) fn same_phones(&self, a: &[String], b: &[String]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| self.same_phone(a, b))
+    }
+
+    /// Whether two words have the same phones, regardless of the graphs and
+    /// separators they were parsed with.
+    #[must_use]
+    pub fn phones_eq(&self, other: &Word) -> bool {
+        self.phones == other.phones
+    }
+
+    /// Whether two words have the same phones, like [`Word::phones_eq`], but
+    /// also ignoring word boundaries, so that `a b` and `ab` are equal.
+    #[must_use]
+    pub fn phones_eq_without_boundaries(&self, other: &Word) -> bool {
+        let without_boundaries = |word: &Word| {
+            word.phones
+                .iter()
+                .filter(|phone| *phone != "#")
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        without_boundaries(self) == without_boundaries(other)
+    }
+
+    /// Renders the word for presentation, mapping each phone to its surface
+    /// symbol through `symbols`. Phones without a symbol are rendered as they
+    /// are, and word boundaries become spaces.
+    ///
+    /// This keeps the abstract phones the rules operate on separate from how
+    /// they're finally written, e.g. rules can work on `N` while the output
+    /// shows `ŋ`. The symbols of an orthography can be made with
+    /// [`build_output_map`].
+    #[must_use]
+    pub fn render(&self, symbols: &HashMap<String, String>) -> String {
+        let mut as_str = String::new();
+        for (index, phone) in self.phones.iter().enumerate() {
+            if phone == "#" {
+                as_str.push(' ');
+            } else {
+                if self.syllable_breaks.contains(&index) {
+                    as_str.push('.');
+                }
+                as_str.push_str(symbols.get(phone).unwrap_or(phone));
+            }
+        }
+        as_str.trim().to_string()
+    }
+}
+
+/// A pattern whose text has been split into phones ahead of time, such that
+/// each `Text` element matches exactly one phone. Matching a plain [`Pattern`]
+/// splits its text again every time, so a pattern matched at every index of a
+/// word, or against many words, should be compiled once instead.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompiledPattern {
+    elements: Vec<PatternElement>,
+}
+
+impl CompiledPattern {
+    /// Compiles a pattern for words parsed with the given graphs and
+    /// separators.
+    #[must_use]
+    pub fn new(pattern: &Pattern, graphs: &[String], separators: &[String]) -> Self {
+        CompiledPattern {
+            elements: flatten(&pattern.elements, graphs, separators),
+        }
+    }
+}
+
+/// Splits any text into its phones, such that each `Text` element matches
+/// exactly one phone.
+fn flatten(
+    elements: &[PatternElement],
+    graphs: &[String],
+    separators: &[String],
+) -> Vec<PatternElement> {
+    use crate::parse::PatternElement::*;
+
+    let flatten = |p: &Pattern| Pattern {
+        elements: flatten(&p.elements, graphs, separators),
+    };
+    elements
+        .iter()
+        .flat_map(|e| match e {
+            Text(t) => into_phones(t.clone(), graphs, separators)
+                .into_iter()
+                .map(Text)
+                .collect(),
+            Optional(p) => vec![Optional(flatten(p))],
+            OptionalNonGreedy(p) => vec![OptionalNonGreedy(flatten(p))],
+            NegativeLookahead(p) => vec![NegativeLookahead(flatten(p))],
+            NegativeLookbehind(p) => vec![NegativeLookbehind(flatten(p))],
+            _ => vec![e.clone()],
+        })
+        .collect()
+}
+
+/// How many times a repetition repeats the element before it.
+struct Bounds {
+    min: usize,
+    max: Option<usize>,
+    /// Whether more repetitions are preferred over fewer.
+    greedy: bool,
+    /// Whether the repetitions may cross word boundaries.
+    extended: bool,
+}
+
+impl Bounds {
+    /// `{n}` means the element occurs exactly `n` times in total, such that
+    /// `a{3}` is equivalent to `aaa`, and `a{0}` matches nothing at all.
+    /// `{m,n}` means it occurs between `m` and `n` times, as many as the rest
+    /// of the pattern allows, and `{m,}` at least `m` times.
+    ///
+    /// `{*}` and friends mean the element occurs one or more times, with the
+    /// same greediness and boundary rules as the equivalent bare wildcard.
+    fn of(repeat: &PatternElement) -> Option<Self> {
+        use crate::parse::PatternElement::*;
+
+        match repeat {
+            RepeatN(n) => Some(Bounds {
+                min: *n,
+                max: Some(*n),
+                greedy: true,
+                extended: true,
+            }),
+            RepeatRange { min, max } => Some(Bounds {
+                min: *min,
+                max: *max,
+                greedy: true,
+                extended: true,
+            }),
+            RepeatWild(wildcard) => Some(Bounds {
+                min: 1,
+                max: None,
+                greedy: wildcard.is_greedy(),
+                extended: wildcard.is_extended(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The state needed to match patterns against a single word.
+struct Matcher<'a> {
+    word: &'a Word,
+    categories: &'a HashMap<String, Category>,
+    predicates: &'a Predicates,
+}
+
+impl Matcher<'_> {
+    /// Matches `elements[position..]` against the phones starting at `index`,
+    /// backtracking into earlier choices whenever `k` rejects a match.
+    #[allow(clippy::range_plus_one)] // whyyyy is RangeInclusive a different type
+    fn match_elements(
+        &self,
+        elements: &[PatternElement],
+        position: usize,
+        index: usize,
+        mut matches: Vec<Match>,
+        k: &mut Continuation,
+    ) -> bool {
+        use crate::parse::PatternElement::*;
+
+        // past the end of the word, not even an empty pattern matches
+        if index > self.word.phones.len() {
+            return false;
+        }
+        let Some(element) = elements.get(position) else {
+            return k(index, matches);
+        };
+
+        if let Some(RepeatN(_) | RepeatRange { .. } | RepeatWild(_)) = elements.get(position + 1) {
+            return self.match_repeat(elements, position, index, &matches, k);
+        }
 
         // TODO: could be more rusty
 
-        for (element_index, element) in pattern.into_iter().enumerate() {
-            let phone = &self.phones[index];
-            match element {
-                Text(graph) => {
-                    println!("{graph:?} == {phone:?}");
-                    if &graph != phone {
-                        return None;
-                    }
-                    matches.push(Match::Single(SingleMatch {
-                        range: last_index..(index + 1),
-                        element: Text(graph),
-                    }));
+        match element {
+            Optional(_) => self.match_optional(true, elements, position, index, &matches, k),
+            OptionalNonGreedy(_) => {
+                self.match_optional(false, elements, position, index, &matches, k)
+            }
+            Text(graph) => {
+                let Some(phone) = self.word.phones.get(index) else {
+                    return false;
+                };
+                log::trace!("{graph:?} == {phone:?}");
+                if !self.word.same_phone(graph, phone) {
+                    return false;
                 }
-                Ditto => {
-                    if element_index == 0 || phone != &self.phones[element_index - 1] {
-                        return None;
-                    }
+                self.match_phone(elements, position, index, matches, k)
+            }
+            Ditto => {
+                if !self.word.repeats_previous(index) {
+                    return false;
+                }
+                self.match_phone(elements, position, index, matches, k)
+            }
+            Boundary => {
+                if self.word.phones.get(index).is_none_or(|phone| phone != "#") {
+                    return false;
+                }
+                self.match_phone(elements, position, index, matches, k)
+            }
+            OptionalBoundary => {
+                // prefer consuming the boundary, so that nothing else can match it
+                if self
+                    .word
+                    .phones
+                    .get(index)
+                    .is_some_and(|phone| phone == "#")
+                {
+                    let mut matches = matches.clone();
                     matches.push(Match::Single(SingleMatch {
-                        range: last_index..(index + 1),
-                        element,
+                        range: index..(index + 1),
+                        element: element.clone(),
                     }));
+                    if self.match_elements(elements, position + 1, index + 1, matches, k) {
+                        return true;
+                    }
+                }
+                matches.push(Match::Single(SingleMatch {
+                    range: index..index,
+                    element: element.clone(),
+                }));
+                self.match_elements(elements, position + 1, index, matches, k)
+            }
+            Wildcard(wildcard) => {
+                self.match_wildcard(wildcard, elements, position, index, &matches, k)
+            }
+            CatRef(_) | Category(_) => self.match_category(elements, position, index, &matches, k),
+            NegatedCategory(members) => {
+                self.match_negated_category(members, elements, position, index, matches, k)
+            }
+            PredicateRef(name) => {
+                let holds = self.word.phones.get(index).is_some_and(|phone| {
+                    phone != "#" && self.predicates.get(name).is_some_and(|p| p(phone))
+                });
+                if !holds {
+                    return false;
+                }
+                self.match_phone(elements, position, index, matches, k)
+            }
+            NegativeLookahead(_) | NegativeLookbehind(_) | Gap | SyllableBoundary => {
+                self.match_assertion(elements, position, index, matches, k)
+            }
+            // a repetition with nothing before it to repeat can't match anything,
+            // nor can a repeat count or a reference to the target, which only
+            // mean something in a change, the only place the parser allows them
+            RepeatN(_)
+            | RepeatRange { .. }
+            | RepeatWild(_)
+            | RepeatCount(_)
+            | Target
+            | TargetReversed => false,
+        }
+    }
+
+    /// Matches the element at `elements[position]` against the single phone at
+    /// `index`, once it's been found to match.
+    fn match_phone(
+        &self,
+        elements: &[PatternElement],
+        position: usize,
+        index: usize,
+        mut matches: Vec<Match>,
+        k: &mut Continuation,
+    ) -> bool {
+        matches.push(Match::Single(SingleMatch {
+            range: index..(index + 1),
+            element: elements[position].clone(),
+        }));
+        self.match_elements(elements, position + 1, index + 1, matches, k)
+    }
+
+    /// Matches an element which consumes nothing, only checking something about
+    /// the surroundings of `index`.
+    fn match_assertion(
+        &self,
+        elements: &[PatternElement],
+        position: usize,
+        index: usize,
+        mut matches: Vec<Match>,
+        k: &mut Continuation,
+    ) -> bool {
+        let element = &elements[position];
+        let holds = match element {
+            PatternElement::NegativeLookahead(inner) => {
+                !self.match_elements(&inner.elements, 0, index, vec![], &mut |_, _| true)
+            }
+            PatternElement::NegativeLookbehind(inner) => {
+                self.match_ending_at(&inner.elements, index).is_none()
+            }
+            PatternElement::SyllableBoundary => self.word.is_syllable_break(index),
+            // only meaningful in environments, which are split around it
+            // before matching, so elsewhere it just matches nothing
+            _ => true,
+        };
+        if !holds {
+            return false;
+        }
+        matches.push(Match::Single(SingleMatch {
+            range: index..index,
+            element: element.clone(),
+        }));
+        self.match_elements(elements, position + 1, index, matches, k)
+    }
+
+    /// Matches `elements` against a run of phones ending exactly at `end`,
+    /// preferring the shortest such run.
+    fn match_ending_at(&self, elements: &[PatternElement], end: usize) -> Option<Vec<Match>> {
+        let mut result = None;
+        (0..=end).rev().any(|start| {
+            self.match_elements(elements, 0, start, vec![], &mut |e, matches| {
+                if e == end {
+                    result = Some(matches);
                 }
-                _ => todo!(),
+                e == end
+            })
+        });
+        result
+    }
+
+    /// Matches the wildcard at `elements[position]` against one or more phones.
+    #[allow(clippy::range_plus_one)]
+    fn match_wildcard(
+        &self,
+        wildcard: &crate::common::Wildcard,
+        elements: &[PatternElement],
+        position: usize,
+        index: usize,
+        matches: &[Match],
+        k: &mut Continuation,
+    ) -> bool {
+        let phones = &self.word.phones;
+        let mut ends = (index + 1..=phones.len())
+            .take_while(|&end| wildcard.is_extended() || phones[end - 1] != "#")
+            .collect::<Vec<_>>();
+        if wildcard.is_greedy() {
+            ends.reverse();
+        }
+        ends.into_iter().any(|end| {
+            let mut matches = matches.to_vec();
+            matches.push(Match::Multiple(MultipleMatch {
+                range: index..end,
+                element: elements[position].clone(),
+                matches: (index..end)
+                    .map(|i| {
+                        Match::Single(SingleMatch {
+                            range: i..(i + 1),
+                            element: PatternElement::Text(phones[i].clone()),
+                        })
+                    })
+                    .collect(),
+            }));
+            self.match_elements(elements, position + 1, end, matches, k)
+        })
+    }
+
+    /// Matches the category at `elements[position]` against any one of its
+    /// members. The null category `[]` matches nothing, without consuming.
+    fn match_category(
+        &self,
+        elements: &[PatternElement],
+        position: usize,
+        index: usize,
+        matches: &[Match],
+        k: &mut Continuation,
+    ) -> bool {
+        use crate::parse::PatternElement::*;
+
+        let element = &elements[position];
+        let members = match element {
+            Category(members) if members.is_empty() => vec![vec![]],
+            CatRef(_) | Category(_) => category_members(
+                element,
+                self.categories,
+                &self.word.graphs,
+                &self.word.separators,
+            ),
+            _ => unreachable!("match_category called on a non-category element"),
+        };
+
+        members.into_iter().any(|member| {
+            let end = index + member.len();
+            let phones = self.word.phones.get(index..end);
+            if !phones.is_some_and(|phones| self.word.same_phones(phones, &member)) {
+                return false;
             }
-            index += 1;
-            last_index = index;
+            let mut matches = matches.to_vec();
+            matches.push(Match::Single(SingleMatch {
+                range: index..end,
+                element: element.clone(),
+            }));
+            self.match_elements(elements, position + 1, end, matches, k)
+        })
+    }
+
+    /// Matches a single phone which is neither a word boundary nor any of
+    /// `members`, with category references among them expanded first.
+    fn match_negated_category(
+        &self,
+        members: &[CatOrEl],
+        elements: &[PatternElement],
+        position: usize,
+        index: usize,
+        mut matches: Vec<Match>,
+        k: &mut Continuation,
+    ) -> bool {
+        let Some(phone) = self.word.phones.get(index) else {
+            return false;
+        };
+        let excluded = cat_or_els_to_els(
+            members.to_vec(),
+            self.categories,
+            &self.word.graphs,
+            &self.word.separators,
+        );
+        let is_phone =
+            |member: &Vec<String>| self.word.same_phones(member, std::slice::from_ref(phone));
+        if phone == "#" || excluded.iter().any(is_phone) {
+            return false;
         }
+        matches.push(Match::Single(SingleMatch {
+            range: index..(index + 1),
+            element: elements[position].clone(),
+        }));
+        self.match_elements(elements, position + 1, index + 1, matches, k)
+    }
+
+    /// Matches the element at `elements[position]` repeated as many times as
+    /// the repetition following it asks for.
+    fn match_repeat(
+        &self,
+        elements: &[PatternElement],
+        position: usize,
+        index: usize,
+        matches: &[Match],
+        k: &mut Continuation,
+    ) -> bool {
+        let repeated = &elements[position..=position];
+        let repeat = &elements[position + 1];
+        let Some(bounds) = Bounds::of(repeat) else {
+            unreachable!("match_repeat called without a repetition");
+        };
 
-        Some(matches)
+        self.match_repetitions(repeated, &bounds, index, &[], &mut |end, inner| {
+            let mut matches = matches.to_vec();
+            matches.push(Match::Multiple(MultipleMatch {
+                range: index..end,
+                element: repeat.clone(),
+                matches: inner,
+            }));
+            self.match_elements(elements, position + 2, end, matches, k)
+        })
+    }
+
+    /// Matches `repeated` over and over within the given bounds, collecting the
+    /// match of each repetition.
+    fn match_repetitions(
+        &self,
+        repeated: &[PatternElement],
+        bounds: &Bounds,
+        index: usize,
+        repetitions: &[Match],
+        k: &mut Continuation,
+    ) -> bool {
+        let count = repetitions.len();
+
+        let stop = |k: &mut Continuation| count >= bounds.min && k(index, repetitions.to_vec());
+
+        let more = |k: &mut Continuation| {
+            if bounds.max.is_some_and(|max| count >= max) {
+                return false;
+            }
+            self.match_elements(repeated, 0, index, vec![], &mut |end, matches| {
+                // an empty repetition can't make any progress
+                if end == index && count >= bounds.min {
+                    return false;
+                }
+                if !bounds.extended && self.word.phones[index..end].iter().any(|p| p == "#") {
+                    return false;
+                }
+                let mut repetitions = repetitions.to_vec();
+                repetitions.extend(matches);
+                self.match_repetitions(repeated, bounds, end, &repetitions, k)
+            })
+        };
+
+        if bounds.greedy {
+            more(k) || stop(k)
+        } else {
+            let stopped = stop(k);
+            stopped || more(k)
+        }
+    }
+
+    /// Matches the optional element at `elements[position]`. Greedy optionals
+    /// try to match their contents before trying to skip them; non-greedy
+    /// optionals prefer skipping, and only match their contents if the rest of
+    /// the pattern would otherwise fail.
+    fn match_optional(
+        &self,
+        greedy: bool,
+        elements: &[PatternElement],
+        position: usize,
+        index: usize,
+        matches: &[Match],
+        k: &mut Continuation,
+    ) -> bool {
+        use crate::parse::PatternElement::*;
+
+        let element = &elements[position];
+        let (Optional(inner) | OptionalNonGreedy(inner)) = element else {
+            unreachable!("match_optional called on a non-optional element");
+        };
+
+        let consume = |k: &mut Continuation| {
+            self.match_elements(&inner.elements, 0, index, vec![], &mut |end, inner| {
+                let mut matches = matches.to_vec();
+                matches.push(Match::Multiple(MultipleMatch {
+                    range: index..end,
+                    element: element.clone(),
+                    matches: inner,
+                }));
+                self.match_elements(elements, position + 1, end, matches, k)
+            })
+        };
+
+        let skip = |k: &mut Continuation| {
+            let mut matches = matches.to_vec();
+            matches.push(Match::Multiple(MultipleMatch {
+                range: index..index,
+                element: element.clone(),
+                matches: vec![],
+            }));
+            self.match_elements(elements, position + 1, index, matches, k)
+        };
+
+        if greedy {
+            consume(k) || skip(k)
+        } else {
+            let skipped = skip(k);
+            skipped || consume(k)
+        }
     }
 }
 
 #[cfg(test)]
 mod match_tests {
+    use std::collections::HashMap;
+
     use chumsky::Parser;
 
     #[test]
@@ -116,12 +913,14 @@ mod match_tests {
                 String::from("#"),
             ],
             graphs: vec![],
-            separator: String::from("'"),
+            separators: vec![String::from("'")],
+            syllable_breaks: vec![],
+            case_insensitive: false,
         };
 
         let pattern = crate::parse::pattern().parse("abc").into_output().unwrap();
 
-        let matches = word.match_one(&pattern, 1).unwrap();
+        let matches = word.match_one(&pattern, &HashMap::new(), 1).unwrap();
 
         assert_eq!(
             matches,
@@ -141,42 +940,350 @@ mod match_tests {
             ]
         );
     }
+
+    #[test]
+    fn optional_greediness() {
+        use super::{Match, MultipleMatch, SingleMatch};
+        use crate::parse::PatternElement::*;
+
+        let word = super::parse("aa", vec![], vec![String::from("'")]);
+        let a = || Text(String::from("a"));
+
+        let greedy = crate::parse::pattern().parse("(a)a").into_output().unwrap();
+        let non_greedy = crate::parse::pattern()
+            .parse("(a)?a")
+            .into_output()
+            .unwrap();
+
+        assert_eq!(
+            word.match_one(&greedy, &HashMap::new(), 1).unwrap(),
+            vec![
+                Match::Multiple(MultipleMatch {
+                    range: 1..2,
+                    element: greedy.elements[0].clone(),
+                    matches: vec![Match::Single(SingleMatch {
+                        range: 1..2,
+                        element: a(),
+                    })],
+                }),
+                Match::Single(SingleMatch {
+                    range: 2..3,
+                    element: a(),
+                }),
+            ]
+        );
+
+        assert_eq!(
+            word.match_one(&non_greedy, &HashMap::new(), 1).unwrap(),
+            vec![
+                Match::Multiple(MultipleMatch {
+                    range: 1..1,
+                    element: non_greedy.elements[0].clone(),
+                    matches: vec![],
+                }),
+                Match::Single(SingleMatch {
+                    range: 1..2,
+                    element: a(),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn optional_backtracking() {
+        let word = super::parse("ab", vec![], vec![String::from("'")]);
+
+        // the greedy optional has to give up its `a` for the rest to match
+        let pattern = crate::parse::pattern()
+            .parse("(a)ab")
+            .into_output()
+            .unwrap();
+        let matches = word.match_one(&pattern, &HashMap::new(), 1).unwrap();
+        assert!(matches!(&matches[0], super::Match::Multiple(m) if m.range == (1..1)));
+
+        let pattern = crate::parse::pattern()
+            .parse("(a)?c")
+            .into_output()
+            .unwrap();
+        assert_eq!(word.match_one(&pattern, &HashMap::new(), 1), None);
+    }
+
+    #[test]
+    fn optional_boundary() {
+        let pattern = crate::parse::pattern().parse("a#?b").into_output().unwrap();
+
+        // across a word boundary, in phrase context
+        let phrase = super::parse("a b", vec![], vec![String::from("'")]);
+        let matches = phrase.match_one(&pattern, &HashMap::new(), 1).unwrap();
+        assert_eq!(matches[1].range(), 2..3);
+        assert_eq!(matches[2].range(), 3..4);
+
+        // and word-internally, where it matches nothing
+        let word = super::parse("ab", vec![], vec![String::from("'")]);
+        let matches = word.match_one(&pattern, &HashMap::new(), 1).unwrap();
+        assert_eq!(matches[1].range(), 2..2);
+        assert_eq!(matches[2].range(), 2..3);
+
+        assert_eq!(word.match_one(&pattern, &HashMap::new(), 2), None);
+    }
+
+    #[test]
+    fn repeat_n() {
+        let pattern = crate::parse::pattern().parse("a{3}").into_output().unwrap();
+
+        let word = super::parse("aaa", vec![], vec![String::from("'")]);
+        let matches = word.match_one(&pattern, &HashMap::new(), 1).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].range(), 1..4);
+        let super::Match::Multiple(repeat) = &matches[0] else {
+            panic!("repetition should be a multiple match");
+        };
+        assert_eq!(repeat.matches.len(), 3);
+
+        let word = super::parse("aa", vec![], vec![String::from("'")]);
+        assert_eq!(word.match_one(&pattern, &HashMap::new(), 1), None);
+
+        let pattern = crate::parse::pattern()
+            .parse("ba{0}")
+            .into_output()
+            .unwrap();
+        let word = super::parse("b", vec![], vec![String::from("'")]);
+        let matches = word.match_one(&pattern, &HashMap::new(), 1).unwrap();
+        assert_eq!(matches[1].range(), 2..2);
+    }
+
+    #[test]
+    fn repeat_range() {
+        let pattern = crate::parse::pattern()
+            .parse("#a{2,3}#")
+            .into_output()
+            .unwrap();
+        for (input, matched) in [("a", false), ("aa", true), ("aaa", true), ("aaaa", false)] {
+            let word = super::parse(input, vec![], vec![String::from("'")]);
+            let matches = word.match_one(&pattern, &HashMap::new(), 0);
+            assert_eq!(matches.is_some(), matched, "{input}");
+            if let Some(matches) = matches {
+                assert_eq!(matches[1].repetitions(), Some(input.len()));
+            }
+        }
+
+        let pattern = crate::parse::pattern()
+            .parse("a{2,}")
+            .into_output()
+            .unwrap();
+        let word = super::parse("aaaa", vec![], vec![String::from("'")]);
+        let matches = word.match_one(&pattern, &HashMap::new(), 1).unwrap();
+        assert_eq!(matches[0].range(), 1..5);
+        let word = super::parse("ab", vec![], vec![String::from("'")]);
+        assert_eq!(word.match_one(&pattern, &HashMap::new(), 1), None);
+    }
+
+    #[test]
+    fn dangling_repeat_n() {
+        use crate::parse::PatternElement::*;
+
+        let pattern = crate::parse::Pattern {
+            elements: vec![RepeatN(2), Text(String::from("a"))],
+        };
+        let word = super::parse("aa", vec![], vec![String::from("'")]);
+        assert_eq!(word.match_one(&pattern, &HashMap::new(), 1), None);
+    }
+
+    #[test]
+    fn repeat_wild() {
+        use crate::apply::Category;
+
+        let categories = HashMap::from([(
+            String::from("C"),
+            Category {
+                elements: vec![
+                    vec![String::from("b")],
+                    vec![String::from("c")],
+                    vec![String::from("d")],
+                ],
+            },
+        )]);
+        let word = super::parse("bcda", vec![], vec![String::from("'")]);
+
+        let greedy = crate::parse::pattern()
+            .parse("[C]{*}")
+            .into_output()
+            .unwrap();
+        let matches = word.match_one(&greedy, &categories, 1).unwrap();
+        assert_eq!(matches[0].range(), 1..4);
+
+        let non_greedy = crate::parse::pattern()
+            .parse("[C]{*?}")
+            .into_output()
+            .unwrap();
+        let matches = word.match_one(&non_greedy, &categories, 1).unwrap();
+        assert_eq!(matches[0].range(), 1..2);
+
+        // either way, the rest of the pattern still has to match
+        let pattern = crate::parse::pattern()
+            .parse("[C]{*?}a")
+            .into_output()
+            .unwrap();
+        let matches = word.match_one(&pattern, &categories, 1).unwrap();
+        assert_eq!(matches[0].range(), 1..4);
+
+        let pattern = crate::parse::pattern()
+            .parse("[C]{*}cda")
+            .into_output()
+            .unwrap();
+        let matches = word.match_one(&pattern, &categories, 1).unwrap();
+        assert_eq!(matches[0].range(), 1..2);
+
+        // at least one repetition is needed
+        assert_eq!(word.match_one(&greedy, &categories, 4), None);
+    }
+
+    #[test]
+    fn negative_lookaround() {
+        let word = super::parse("abac", vec![], vec![String::from("'")]);
+
+        let pattern = crate::parse::pattern()
+            .parse("a(?!b)")
+            .into_output()
+            .unwrap();
+        assert_eq!(word.match_one(&pattern, &HashMap::new(), 1), None);
+        let matches = word.match_one(&pattern, &HashMap::new(), 3).unwrap();
+        assert_eq!(matches[1].range(), 4..4);
+
+        let pattern = crate::parse::pattern()
+            .parse("(?<!b)a")
+            .into_output()
+            .unwrap();
+        assert!(word.match_one(&pattern, &HashMap::new(), 1).is_some());
+        assert_eq!(word.match_one(&pattern, &HashMap::new(), 3), None);
+    }
+
+    #[test]
+    fn repeat_wild_boundaries() {
+        let phrase = super::parse("ab ba", vec![], vec![String::from("'")]);
+
+        let pattern = crate::parse::pattern()
+            .parse("[a,b,#]{*}")
+            .into_output()
+            .unwrap();
+        let matches = phrase.match_one(&pattern, &HashMap::new(), 1).unwrap();
+        assert_eq!(matches[0].range(), 1..3);
+
+        let pattern = crate::parse::pattern()
+            .parse("[a,b,#]{**}")
+            .into_output()
+            .unwrap();
+        let matches = phrase.match_one(&pattern, &HashMap::new(), 1).unwrap();
+        assert_eq!(matches[0].range(), 1..7);
+    }
+}
+
+/// How a word was capitalised, so that the same capitalisation can be given to
+/// what it becomes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Casing {
+    Lower,
+    /// Only the first letter is uppercase, as in `Apfel`.
+    Title,
+    /// Every letter is uppercase, as in `APFEL`.
+    Upper,
+}
+
+impl Casing {
+    /// Finds the capitalisation of some text. Anything with a mix of cases
+    /// other than title case counts as lowercase.
+    #[must_use]
+    pub fn of(text: &str) -> Self {
+        let mut letters = text.chars().filter(|c| c.is_alphabetic()).peekable();
+        if letters.peek().is_none() {
+            return Casing::Lower;
+        }
+        if letters.clone().all(char::is_uppercase) && text.chars().count() > 1 {
+            Casing::Upper
+        } else if letters.next().is_some_and(char::is_uppercase) && letters.all(char::is_lowercase)
+        {
+            Casing::Title
+        } else {
+            Casing::Lower
+        }
+    }
+
+    /// Capitalises some text the same way.
+    #[must_use]
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            Casing::Lower => text.to_string(),
+            Casing::Upper => text.to_uppercase(),
+            Casing::Title => {
+                let mut chars = text.chars();
+                chars.next().map_or_else(String::new, |first| {
+                    first.to_uppercase().chain(chars).collect()
+                })
+            }
+        }
+    }
+}
+
+impl FromStr for Word {
+    type Err = Infallible;
+
+    /// Parses a word without any graphs, separating phones with `'`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Ok(Word::new(input, &[], &[String::from("'")]))
+    }
 }
 
 impl Display for Word {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if no_polygraphs(&self.graphs) {
-            let mut as_str = String::new();
-            for phone in &self.phones {
-                if phone == "#" {
-                    as_str.push(' ');
-                } else {
-                    as_str.push_str(phone.as_str());
-                }
+        let separator = self.separators.first().map_or("", String::as_str);
+        // written from the end, so that a separator only goes after a phone
+        // when it and what actually follows would be read as a longer graph
+        let mut as_str = String::new();
+        for (index, phone) in self.phones.iter().enumerate().rev() {
+            if phone == "#" {
+                as_str.insert(0, ' ');
+                continue;
             }
-            write!(f, "{}", as_str.trim())
-        } else {
-            let mut as_str = String::new();
-            for phone in &self.phones {
-                if phone == "#" {
-                    as_str.push(' ');
-                } else {
-                    as_str.push_str(phone.as_str());
-                    if self
-                        .graphs
-                        .iter()
-                        .any(|graph| graph.starts_with(phone) && graph != phone)
-                    {
-                        as_str.push_str(&self.separator);
-                    }
-                }
+            let ambiguous = self.graphs.iter().any(|graph| {
+                graph.len() > phone.len()
+                    && graph.starts_with(phone.as_str())
+                    && as_str.starts_with(&graph[phone.len()..])
+            });
+            if ambiguous {
+                as_str.insert_str(0, separator);
+            }
+            as_str.insert_str(0, phone);
+            if self.separators.contains(phone) {
+                as_str.insert(0, '\\');
+            }
+            if self.syllable_breaks.contains(&index) {
+                as_str.insert(0, '.');
             }
-            write!(f, "{}", as_str.trim())
         }
+        write!(f, "{}", as_str.trim())
     }
 }
 
-fn no_polygraphs(graphs: &Vec<String>) -> bool {
+/// Maps each phone back to the graph of an orthography which stands for it,
+/// given the graphs and the phones they stand for, so that
+/// [`Word::render`] can write words out in that orthography. Where several
+/// graphs stand for the same phone, the shortest is taken, or the first of
+/// those.
+#[must_use]
+pub fn build_output_map(graphs_with_values: &[(String, String)]) -> HashMap<String, String> {
+    let mut output = HashMap::<String, String>::new();
+    for (graph, phone) in graphs_with_values {
+        let shorter = output
+            .get(phone)
+            .is_none_or(|chosen| graph.chars().count() < chosen.chars().count());
+        if shorter {
+            output.insert(phone.clone(), graph.clone());
+        }
+    }
+    output
+}
+
+fn no_polygraphs(graphs: &[String]) -> bool {
     for graph in graphs {
         if graph.len() > 1 {
             return false;
@@ -185,78 +1292,256 @@ fn no_polygraphs(graphs: &Vec<String>) -> bool {
     true
 }
 
-pub fn into_phones(input: String, graphs: &Vec<String>, separator: &String) -> Vec<String> {
+/// Splits text into phones, taking the first of `graphs` found at each point,
+/// or a single character otherwise, and skipping `separators`. Modifiers like
+/// the length mark `:` are thus phones of their own, unless part of a graph.
+/// Empty graphs and separators are ignored. A separator escaped with a
+/// backslash, like `\\'`, is a phone rather than skipped.
+#[must_use]
+pub fn into_phones(input: String, graphs: &[String], separators: &[String]) -> Vec<String> {
+    split_phones(input, graphs, separators, false)
+}
+
+/// The length in bytes of `prefix` at the start of `input`, if it's there,
+/// ignoring case if `case_insensitive`.
+fn prefix_len(input: &str, prefix: &str, case_insensitive: bool) -> Option<usize> {
+    if !case_insensitive {
+        return input.starts_with(prefix).then_some(prefix.len());
+    }
+    let mut chars = input.char_indices();
+    for p in prefix.chars() {
+        let (_, c) = chars.next()?;
+        if !c.to_lowercase().eq(p.to_lowercase()) {
+            return None;
+        }
+    }
+    Some(chars.next().map_or(input.len(), |(i, _)| i))
+}
+
+/// Splits text into phones like [`into_phones`], finding graphs ignoring case
+/// if `case_insensitive`, though the phones keep the case of the text.
+fn split_phones(
+    input: String,
+    graphs: &[String],
+    separators: &[String],
+    case_insensitive: bool,
+) -> Vec<String> {
     let mut phones: Vec<String> = vec![];
     let mut input = input;
 
     while !input.is_empty() {
-        if input.starts_with(separator) {
-            input = input.split_once(separator).unwrap().1.to_string();
+        // a backslash before a separator makes it a phone of its own
+        if let Some(escaped) = input.strip_prefix('\\') {
+            if let Some(separator) = separators
+                .iter()
+                .find(|separator| !separator.is_empty() && escaped.starts_with(separator.as_str()))
+            {
+                phones.push(separator.clone());
+                input = escaped[separator.len()..].to_string();
+                continue;
+            }
+        }
+
+        if let Some(separator) = separators
+            .iter()
+            .find(|separator| !separator.is_empty() && input.starts_with(separator.as_str()))
+        {
+            input = input[separator.len()..].to_string();
+            continue;
         }
 
-        let graph = graphs.iter().find(|g| input.starts_with(g.as_str()));
+        // an empty graph or separator would match without consuming anything
+        let graph = graphs
+            .iter()
+            .filter(|g| !g.is_empty())
+            .find_map(|g| prefix_len(&input, g, case_insensitive));
 
-        if let Some(graph) = graph {
-            let len = graph.len();
-            phones.push(graph.to_string());
+        if let Some(len) = graph {
+            phones.push(input[..len].to_string());
             input = input[len..].to_string();
         } else {
-            let first = input.split_at(1).0;
-            phones.push(first.to_string());
-            input = input[1..].to_string();
+            let len = input.chars().next().map_or(1, char::len_utf8);
+            phones.push(input[..len].to_string());
+            input = input[len..].to_string();
         }
     }
 
     phones
 }
 
-/// Parses an input string into a word.
+/// A Unicode normalization form to bring words and graphs into before they're
+/// split into phones, so that a letter spelled precomposed in one place and
+/// decomposed in another is still the same phone.
+#[cfg(feature = "unicode-normalization")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Normalization {
+    /// Leave everything as given.
+    #[default]
+    None,
+    /// Canonical composition, so `e` followed by a combining macron is `ē`.
+    Nfc,
+    /// Canonical decomposition, so `ē` is `e` followed by a combining macron.
+    Nfd,
+}
+
+#[cfg(feature = "unicode-normalization")]
+impl Normalization {
+    /// The input in this normalization form.
+    #[must_use]
+    pub fn normalize(self, input: &str) -> String {
+        use unicode_normalization::UnicodeNormalization;
+
+        match self {
+            Normalization::None => input.to_string(),
+            Normalization::Nfc => input.nfc().collect(),
+            Normalization::Nfd => input.nfd().collect(),
+        }
+    }
+
+    /// Each of the strings in this normalization form.
+    #[must_use]
+    pub fn normalize_all(self, strings: &[String]) -> Vec<String> {
+        strings.iter().map(|s| self.normalize(s)).collect()
+    }
+}
+
+/// Parses an input string into a word like [`parse`], after bringing it, the
+/// graphs and the separators into the given normalization form.
+#[cfg(feature = "unicode-normalization")]
+#[must_use]
+pub fn parse_normalized(
+    input: &str,
+    graphs: &[String],
+    separators: &[String],
+    normalization: Normalization,
+) -> Word {
+    parse(
+        &normalization.normalize(input),
+        normalization.normalize_all(graphs),
+        normalization.normalize_all(separators),
+    )
+}
+
+/// Parses an input string into a word. A separator escaped with a backslash
+/// is a phone of the word; see [`into_phones`].
 /// Takes ownership of the `graphs` in order to preserve which ones were the ones
 /// used to parse the word.
 ///
 /// ## Returns
 ///
 /// The resultant `Word`.
-pub fn parse(input: &String, mut graphs: Vec<String>, separator: String) -> Word {
+#[must_use]
+pub fn parse(input: &str, graphs: Vec<String>, separators: Vec<String>) -> Word {
+    parse_with(input, graphs, separators, true, false, false)
+}
+
+/// Parses an input string into a word like [`parse`], but without word
+/// boundaries around it, for fragments like affixes which aren't whole words.
+/// Environments with `#` then don't match at its edges.
+#[must_use]
+pub fn parse_fragment(input: &str, graphs: Vec<String>, separators: Vec<String>) -> Word {
+    parse_with(input, graphs, separators, false, false, false)
+}
+
+/// Parses an input string into a word like [`parse`], finding graphs in it
+/// ignoring case, and matching patterns against it ignoring case too. The
+/// phones keep their case, so `SHa` with the graph `sh` is `SH` and `a`.
+#[must_use]
+pub fn parse_case_insensitive(input: &str, graphs: Vec<String>, separators: Vec<String>) -> Word {
+    parse_with(input, graphs, separators, true, true, false)
+}
+
+/// Parses an input string into a word like [`parse`], taking each `.` in it
+/// as a break between syllables rather than a phone, so that `ta.ka` is the
+/// phones `t`, `a`, `k` and `a` with a break before the `k`. Patterns match
+/// the breaks with `.`, and the word is written out with them.
+#[must_use]
+pub fn parse_syllabified(input: &str, graphs: Vec<String>, separators: Vec<String>) -> Word {
+    parse_with(input, graphs, separators, true, false, true)
+}
+
+/// Parses an input string into a word, with word boundaries around it if
+/// `boundaries`, ignoring case if `case_insensitive`, and with each `.` a
+/// syllable break if `syllables`.
+pub(crate) fn parse_with(
+    input: &str,
+    mut graphs: Vec<String>,
+    separators: Vec<String>,
+    boundaries: bool,
+    case_insensitive: bool,
+    syllables: bool,
+) -> Word {
     let input = input.split_whitespace().collect::<Vec<_>>().join("#");
-    let input = format!("#{input}#");
+    let input = if boundaries {
+        format!("#{input}#")
+    } else {
+        input
+    };
 
     graphs.sort_by_cached_key(String::len);
     graphs.reverse();
 
-    if no_polygraphs(&graphs) {
-        let phones = input
-            .split("")
-            .filter(|s| !s.is_empty() && s != &separator.as_str())
-            .map(ToString::to_string)
-            .collect();
-        return Word {
-            phones,
-            graphs,
-            separator,
+    // splitting into characters only finds separators of a single character
+    let single_separators = separators.iter().all(|s| s.chars().count() <= 1);
+    let mut phones: Vec<String> =
+        if no_polygraphs(&graphs) && single_separators && !input.contains('\\') {
+            input
+                .split("")
+                .filter(|s| !s.is_empty() && !separators.iter().any(|separator| separator == s))
+                .map(ToString::to_string)
+                .collect()
+        } else {
+            split_phones(input, &graphs, &separators, case_insensitive)
         };
-    }
+    // a `#` written in the input already is a boundary, and `_#` should
+    // match the same however many of them there are in a row
+    phones.dedup_by(|a, b| a == "#" && b == "#");
 
-    let phones = into_phones(input, &graphs, &separator);
+    let (phones, syllable_breaks) = if syllables {
+        without_syllable_breaks(phones)
+    } else {
+        (phones, vec![])
+    };
 
     Word {
         phones,
         graphs,
-        separator,
+        separators,
+        syllable_breaks,
+        case_insensitive,
     }
 }
 
+/// Takes the `.`s separating syllables out of the phones, returning the rest
+/// of the phones and the indices of those which begin a syllable.
+fn without_syllable_breaks(input: Vec<String>) -> (Vec<String>, Vec<usize>) {
+    let mut phones = vec![];
+    let mut syllable_breaks = vec![];
+    for phone in input {
+        if phone == "." {
+            syllable_breaks.push(phones.len());
+        } else {
+            phones.push(phone);
+        }
+    }
+    (phones, syllable_breaks)
+}
+
 #[cfg(test)]
 mod word_tests {
+    use std::collections::HashMap;
+
+    use chumsky::Parser;
+
     use super::parse;
 
     #[test]
     fn basic() {
         let input = String::from("abc");
         let graphs = vec![];
-        let separator = String::from("'");
+        let separators = vec![String::from("'")];
 
-        let word = parse(&input, graphs, separator);
+        let word = parse(&input, graphs, separators);
 
         assert_eq!(
             word.phones,
@@ -271,13 +1556,25 @@ mod word_tests {
     }
 
     #[cfg(test)]
+    #[test]
+    fn from_str() {
+        let word = "abc".parse::<super::Word>().unwrap();
+        assert_eq!(word.phones, ["#", "a", "b", "c", "#"].map(String::from));
+        assert_eq!(word.separators, vec![String::from("'")]);
+
+        let graphs = [String::from("sh")];
+        let word = super::Word::new("ash", &graphs, &[String::from("'")]);
+        assert_eq!(word, parse("ash", graphs.to_vec(), vec![String::from("'")]));
+        assert_eq!(word.phones, ["#", "a", "sh", "#"].map(String::from));
+    }
+
     #[test]
     fn unnecessary_separator() {
         let input = String::from("a'bc");
         let graphs = vec![];
-        let separator = String::from("'");
+        let separators = vec![String::from("'")];
 
-        let word = parse(&input, graphs, separator);
+        let word = parse(&input, graphs, separators);
 
         assert_eq!(
             word.phones,
@@ -296,11 +1593,11 @@ mod word_tests {
     #[cfg(test)]
     #[test]
     fn polygraphs() {
-        let input = "atshu".into();
+        let input: String = "atshu".into();
         let graphs = vec!["sh".into(), "ts".into(), "tsh".into()];
-        let separator = String::from("'");
+        let separators = vec![String::from("'")];
 
-        let word = parse(&input, graphs, separator);
+        let word = parse(&input, graphs, separators);
 
         assert_eq!(
             word.phones,
@@ -315,11 +1612,11 @@ mod word_tests {
 
         assert_eq!(word.to_string(), input);
 
-        let input = "ats'hu".into();
+        let input: String = "ats'hu".into();
         let graphs = vec!["sh".into(), "ts".into(), "tsh".into()];
-        let separator = String::from("'");
+        let separators = vec![String::from("'")];
 
-        let word = parse(&input, graphs, separator);
+        let word = parse(&input, graphs, separators);
 
         assert_eq!(
             word.phones,
@@ -334,14 +1631,48 @@ mod word_tests {
         );
 
         assert_eq!(word.to_string(), input);
+
+        // only where leaving the separator out would change the phones
+        let graphs: Vec<String> = vec!["sh".into(), "ts".into(), "tsh".into()];
+        let separators = vec![String::from("'")];
+        for input in ["tasit", "sat", "t'sa", "at'sh", "ts'h", "s'ha tsa"] {
+            let word = parse(input, graphs.clone(), separators.clone());
+            assert_eq!(word.to_string(), input);
+        }
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn multiple_separators() {
+        let graphs = vec!["sh".into(), "ts".into()];
+        let separators = vec![String::from("'"), String::from(".")];
+
+        let word = parse("ats'hut.sa", graphs, separators);
+
+        assert_eq!(
+            word.phones,
+            ["#", "a", "ts", "h", "u", "t", "s", "a", "#"].map(String::from)
+        );
+
+        let word = parse("t'a.e", vec![], vec![String::from("'"), String::from(".")]);
+
+        assert_eq!(word.phones, ["#", "t", "a", "e", "#"].map(String::from));
+        assert!(word.syllable_breaks.is_empty());
+
+        // a separator longer than a character is skipped whatever the graphs
+        let separators = vec![String::from("--"), String::from("'")];
+        for graphs in [vec![], vec![String::from("sh")]] {
+            let word = parse("a--b'c", graphs, separators.clone());
+            assert_eq!(word.phones, ["#", "a", "b", "c", "#"].map(String::from));
+        }
     }
 
     #[cfg(test)]
     #[test]
     fn internal_whitespace() {
-        let input = "a  b".into();
+        let input: String = "a  b".into();
 
-        let word = parse(&input, vec![], String::from("'"));
+        let word = parse(&input, vec![], vec![String::from("'")]);
 
         assert_eq!(
             word.phones,
@@ -353,5 +1684,342 @@ mod word_tests {
                 String::from("#")
             ]
         );
+
+        for input in ["a  #  b", "#a#b#", "a##b", " # a # b # "] {
+            let word = parse(input, vec![], vec![String::from("'")]);
+            assert_eq!(word.phones, ["#", "a", "#", "b", "#"], "{input:?}");
+        }
+    }
+
+    #[test]
+    fn fragment() {
+        let fragment = super::parse_fragment("str", vec![], vec![String::from("'")]);
+
+        assert_eq!(fragment.phones, ["s", "t", "r"].map(String::from));
+        assert_eq!(fragment.to_string(), "str");
+
+        let pattern = crate::parse::pattern().parse("r#").into_output().unwrap();
+        assert_eq!(fragment.match_one(&pattern, &HashMap::new(), 2), None);
+    }
+
+    #[test]
+    fn compiled() {
+        let separators = vec![String::from("'")];
+        let graphs = vec![String::from("sh")];
+        let word = parse("ashasha", graphs.clone(), separators.clone());
+        let pattern = crate::parse::pattern()
+            .parse("sha(s)")
+            .into_output()
+            .unwrap();
+        let compiled = super::CompiledPattern::new(&pattern, &graphs, &separators);
+
+        for index in 0..word.phones.len() {
+            assert_eq!(
+                word.match_compiled(&compiled, &HashMap::new(), &HashMap::new(), index),
+                word.match_one(&pattern, &HashMap::new(), index),
+            );
+        }
+        assert!(word
+            .match_compiled(&compiled, &HashMap::new(), &HashMap::new(), 2)
+            .is_some());
+    }
+
+    #[test]
+    fn empty_separator() {
+        let empty = vec![String::new()];
+        let word = parse("ab'c", vec![], empty.clone());
+        assert_eq!(word.phones, vec!["#", "a", "b", "'", "c", "#"]);
+
+        let word = parse(
+            "tsab",
+            vec![String::from("ts"), String::new()],
+            empty.clone(),
+        );
+        assert_eq!(word.phones, vec!["#", "ts", "a", "b", "#"]);
+        assert_eq!(word.to_string(), "tsab");
+
+        let phones = super::into_phones(String::from("ab"), &empty, &empty);
+        assert_eq!(phones, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn escaped_separator() {
+        let separators = vec![String::from("'")];
+        let word = parse("a\\'b", vec![], separators.clone());
+        assert_eq!(word.phones, vec!["#", "a", "'", "b", "#"]);
+        assert_eq!(word.to_string(), "a\\'b");
+
+        let word = parse("t\\'s'h", vec![String::from("sh")], separators.clone());
+        assert_eq!(word.phones, vec!["#", "t", "'", "s", "h", "#"]);
+
+        // a backslash before anything else is left as it is
+        let phones = super::into_phones(String::from("a\\b"), &[], &separators);
+        assert_eq!(phones, vec!["a", "\\", "b"]);
+    }
+
+    #[test]
+    fn find_all() {
+        let pattern = crate::parse::pattern().parse("a").into_output().unwrap();
+        let word = parse("aba", vec![], vec![String::from("'")]);
+        let found = word.find_all(&pattern, &HashMap::new());
+        let ranges: Vec<_> = found.iter().map(|matches| matches[0].range()).collect();
+        assert_eq!(ranges, vec![1..2, 3..4]);
+        let first = word.find_first(&pattern, &HashMap::new()).unwrap();
+        assert_eq!(first[0].range(), 1..2);
+
+        // matches don't overlap, and take as much as they prefer
+        let pattern = crate::parse::pattern().parse("a*").into_output().unwrap();
+        let word = parse("aab aa", vec![], vec![String::from("'")]);
+        let found = word.find_all(&pattern, &HashMap::new());
+        let ends: Vec<_> = found
+            .iter()
+            .map(|matches| matches.last().unwrap().range().end)
+            .collect();
+        assert_eq!(ends, vec![4, 7]);
+
+        let pattern = crate::parse::pattern().parse("c").into_output().unwrap();
+        assert!(word.find_all(&pattern, &HashMap::new()).is_empty());
+        assert_eq!(word.find_first(&pattern, &HashMap::new()), None);
+    }
+
+    #[test]
+    fn out_of_range() {
+        let word = parse("abc", vec![], vec![String::from("'")]);
+        let categories = HashMap::new();
+        let pattern = crate::parse::pattern().parse("abcd").into_output().unwrap();
+        for index in 0..8 {
+            assert_eq!(word.match_one(&pattern, &categories, index), None);
+        }
+
+        // even patterns which can match nothing don't match past the end
+        for pattern in ["bc\"", "#?_", "(x)"] {
+            let pattern = crate::parse::pattern()
+                .parse(pattern)
+                .into_output()
+                .unwrap();
+            for index in 6..8 {
+                assert_eq!(word.match_one(&pattern, &categories, index), None);
+            }
+            for end in 6..8 {
+                assert_eq!(word.match_ending_at(&pattern, &categories, end), None);
+            }
+        }
+    }
+
+    #[test]
+    fn ditto() {
+        let separators = vec![String::from("'")];
+        let cases = [
+            ("a\"", "aa", Some(1)),
+            ("a\"", "ab", None),
+            // with the word's boundary, the pattern's indices lag behind
+            ("ta\"", "kitaa", Some(3)),
+            ("ta\"", "kitab", None),
+            ("\"", "aa", Some(2)),
+        ];
+        for (pattern, word, expected) in cases {
+            let word = parse(word, vec![], separators.clone());
+            let pattern = crate::parse::pattern()
+                .parse(pattern)
+                .into_output()
+                .unwrap();
+            let found = (0..word.phones.len())
+                .find(|&index| word.match_one(&pattern, &HashMap::new(), index).is_some());
+            assert_eq!(found, expected, "{pattern:?} in {word}");
+        }
+    }
+
+    #[test]
+    fn phones_eq() {
+        let word = parse("tsa", vec![String::from("ts")], vec![String::from("'")]);
+        let other = parse(
+            "tsa",
+            vec![String::from("ts"), String::from("x")],
+            vec![String::from(".")],
+        );
+
+        assert_ne!(word, other);
+        assert!(word.phones_eq(&other));
+
+        let phrase = parse("ts a", vec![String::from("ts")], vec![String::from("'")]);
+        assert!(!word.phones_eq(&phrase));
+        assert!(word.phones_eq_without_boundaries(&phrase));
+    }
+
+    #[test]
+    fn casing() {
+        use super::Casing;
+
+        let cases = [
+            ("apfel", Casing::Lower, "apfel"),
+            ("Apfel", Casing::Title, "Apfel"),
+            ("APFEL", Casing::Upper, "APFEL"),
+            ("ApFel", Casing::Lower, "apfel"),
+            ("A", Casing::Title, "A"),
+            ("", Casing::Lower, ""),
+        ];
+        for (text, casing, restored) in cases {
+            assert_eq!(Casing::of(text), casing, "{text}");
+            assert_eq!(casing.apply(&text.to_lowercase()), restored, "{text}");
+        }
+    }
+
+    #[test]
+    fn render() {
+        let word = parse("tAN kA", vec![], vec![String::from("'")]);
+        let symbols = HashMap::from([
+            (String::from("A"), String::from("ɑ")),
+            (String::from("N"), String::from("ŋ")),
+        ]);
+
+        assert_eq!(word.render(&symbols), "tɑŋ kɑ");
+        assert_eq!(word.render(&HashMap::new()), word.to_string());
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn normalized() {
+        use super::{parse_normalized, Normalization};
+
+        // a decomposed graph still joins a precomposed letter with what follows
+        let graphs = vec![String::from("e\u{304}i")];
+        let word = parse_normalized("k\u{113}it", &graphs, &[], Normalization::Nfd);
+        assert_eq!(word.phones, vec!["#", "k", "e\u{304}i", "t", "#"]);
+
+        let word = parse_normalized("k\u{113}it", &graphs, &[], Normalization::None);
+        assert_eq!(word.phones, vec!["#", "k", "\u{113}", "i", "t", "#"]);
+    }
+
+    #[test]
+    fn output_map() {
+        let orthography = [
+            ("sch", "S"),
+            ("sh", "S"),
+            ("ch", "S"),
+            ("ng", "N"),
+            ("a", "a"),
+        ]
+        .map(|(graph, phone)| (String::from(graph), String::from(phone)));
+        let output = super::build_output_map(&orthography);
+
+        assert_eq!(output["S"], "sh");
+        assert_eq!(output["N"], "ng");
+        assert_eq!(output.len(), 3);
+
+        let word = parse("SaN kaS", vec![], vec![String::from("'")]);
+        assert_eq!(word.render(&output), "shang kash");
+    }
+
+    #[test]
+    fn apply_rule() {
+        use crate::{apply::ApplyError, parse::Rule};
+
+        let rule = Rule::builder().target("a").change("b").build().unwrap();
+        let word = parse("abca", vec![], vec![String::from("'")]);
+
+        let applied = word.apply_rule(&rule, &HashMap::new()).unwrap();
+
+        assert_eq!(applied.to_string(), "bbcb");
+        assert_eq!(word.to_string(), "abca");
+
+        let undefined = Rule::builder().target("[V]").change("b").build().unwrap();
+        let error = word.apply_rule(&undefined, &HashMap::new()).unwrap_err();
+        assert_eq!(
+            error,
+            ApplyError::UndefinedCategory {
+                rule: 0,
+                word: String::from("abca"),
+                category: String::from("V"),
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod bench {
+    extern crate test;
+    use std::collections::HashMap;
+
+    use chumsky::Parser;
+    use test::Bencher;
+
+    use super::{parse, CompiledPattern, Word};
+    use crate::{
+        apply::{inventory, Category},
+        parse::{bench::SAXONISH, ASTElement, Pattern},
+    };
+
+    /// The targets of the Saxonish rules, the categories they refer to, and
+    /// some Proto-Germanic words to match them against.
+    fn corpus() -> (Vec<Pattern>, HashMap<String, Category>, Vec<Word>) {
+        let ast = crate::parse::ast().parse(SAXONISH).into_output().unwrap();
+        let mut targets = vec![];
+        let mut edits = vec![];
+        for (element, _) in ast.elements {
+            match element {
+                ASTElement::Rule(rule) => targets.extend(rule.target.patterns),
+                ASTElement::CatEdit(edit) => edits.push(edit),
+                _ => {}
+            }
+        }
+        let separators = vec![String::from("'")];
+        let categories = inventory(edits, vec![], separators.clone()).categories;
+        let words = [
+            "wurdą",
+            "dagaz",
+            "hlaibaz",
+            "stainaz",
+            "gastiz",
+            "sunuz",
+            "haubidą",
+            "fōtuz",
+            "twai",
+            "hwaþeraz",
+            "bindaną",
+            "skipą",
+        ]
+        .iter()
+        .map(|word| parse(word, vec![], separators.clone()))
+        .collect();
+        (targets, categories, words)
+    }
+
+    #[bench]
+    fn match_naive(b: &mut Bencher) {
+        let (targets, categories, words) = corpus();
+        b.iter(|| {
+            for word in &words {
+                for target in &targets {
+                    for index in 0..word.phones.len() {
+                        test::black_box(word.match_one(target, &categories, index));
+                    }
+                }
+            }
+        });
+    }
+
+    #[bench]
+    fn match_compiled(b: &mut Bencher) {
+        let (targets, categories, words) = corpus();
+        let separators = [String::from("'")];
+        let targets = targets
+            .iter()
+            .map(|target| CompiledPattern::new(target, &[], &separators))
+            .collect::<Vec<_>>();
+        let predicates = HashMap::new();
+        b.iter(|| {
+            for word in &words {
+                for target in &targets {
+                    for index in 0..word.phones.len() {
+                        test::black_box(word.match_compiled(
+                            target,
+                            &categories,
+                            &predicates,
+                            index,
+                        ));
+                    }
+                }
+            }
+        });
     }
 }
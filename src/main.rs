@@ -1,12 +1,121 @@
-fn main() {
-    if let Ok(ast) = sce::parse(
-        r#"A = a,b,c
-        A += d
-        A -= c
-        B = d"#,
-    ) {
-        let words = vec!["abc"].iter().map(|s| s.to_string()).collect();
-        sce::apply::apply(ast, words, vec![], String::from("'"));
+use std::{env, fs, process::ExitCode};
+
+const USAGE: &str = "usage: sce [--separator <separator>]... [--graphs <graph,...>] <rules> <words>
+       sce [--separator <separator>]... [--graphs <graph,...>] test <rules> <cases>";
+
+/// The command-line arguments.
+struct Args {
+    /// Every separator given, or just `'` if none were.
+    separators: Vec<String>,
+    graphs: Vec<String>,
+    /// The arguments which aren't flags, in order.
+    positional: Vec<String>,
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        separators: vec![],
+        graphs: vec![],
+        positional: vec![],
+    };
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--separator" => {
+                let separator = args.next().ok_or("--separator needs a value")?;
+                parsed.separators.push(separator);
+            }
+            "--graphs" => {
+                let graphs = args.next().ok_or("--graphs needs a value")?;
+                parsed.graphs = graphs
+                    .split(',')
+                    .filter(|graph| !graph.is_empty())
+                    .map(ToString::to_string)
+                    .collect();
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown flag {flag}")),
+            _ => parsed.positional.push(arg),
+        }
+    }
+    if parsed.separators.is_empty() {
+        parsed.separators.push(String::from("'"));
+    }
+    Ok(parsed)
+}
+
+fn read(path: &str) -> Result<String, ExitCode> {
+    fs::read_to_string(path).map_err(|e| {
+        eprintln!("couldn't read {path}: {e}");
+        ExitCode::FAILURE
+    })
+}
+
+/// Applies the rules in one file to the words in another, one per line,
+/// printing each resulting word.
+fn run(rules: &str, words: &str, args: Args) -> Result<(), ExitCode> {
+    let rules = read(rules)?;
+    let words = read(words)?;
+    let ast = sce::parse(&rules).map_err(|_| ExitCode::FAILURE)?;
+
+    let words = words
+        .lines()
+        .filter(|word| !word.trim().is_empty())
+        .map(ToString::to_string)
+        .collect();
+    let (words, _) = sce::apply::apply(ast, words, args.graphs, args.separators).map_err(|e| {
+        eprintln!("{e}");
+        ExitCode::FAILURE
+    })?;
+
+    for word in words {
+        println!("{word}");
+    }
+    Ok(())
+}
+
+/// Runs `sce test <rules> <cases>`, failing if any of the cases fail.
+fn test(rules: &str, cases: &str, args: Args) -> Result<(), ExitCode> {
+    let rules = read(rules)?;
+    let cases = read(cases)?;
+    let ast = sce::parse(&rules).map_err(|_| ExitCode::FAILURE)?;
+
+    let cases = sce::harness::parse_cases(&cases).map_err(|line| {
+        eprintln!("line {line} of the test cases isn't of the form `input => expected`");
+        ExitCode::FAILURE
+    })?;
+    let report = sce::harness::run(ast, cases, args.graphs, args.separators).map_err(|e| {
+        eprintln!("{e}");
+        ExitCode::FAILURE
+    })?;
+
+    println!("{report}");
+    if report.all_passed() {
+        Ok(())
+    } else {
+        Err(ExitCode::FAILURE)
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let result = match args.positional.clone().as_slice() {
+        [command, rules, cases] if command == "test" => test(rules, cases, args),
+        [rules, words] => run(rules, words, args),
+        _ => {
+            eprintln!("{USAGE}");
+            Err(ExitCode::FAILURE)
+        }
     };
-    println!("Hello, world!");
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(code) => code,
+    }
 }